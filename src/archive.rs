@@ -0,0 +1,120 @@
+use chrono::Utc;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::fs::{copy, create_dir_all, read, remove_file, rename};
+use tracing::info;
+
+use crate::{error::Error, journal::content_hash};
+
+/// Where handled job files are moved to, `processed/` on success and `failed/` otherwise
+#[derive(Debug, Clone)]
+pub struct Archive {
+    path: PathBuf,
+    rename: bool,
+}
+
+impl Archive {
+    /// With `rename`, archived files are prefixed with a timestamp and the job id
+    pub fn new(path: PathBuf, rename: bool) -> Self {
+        Self { path, rename }
+    }
+
+    /// The path the job file will be moved to
+    pub async fn destination(&self, job_path: &Path, success: bool) -> Result<PathBuf, Error> {
+        let directory = self.path.join(if success { "processed" } else { "failed" });
+
+        if !self.rename {
+            return Ok(directory.join(file_name(job_path)));
+        }
+
+        Ok(directory.join(unique_file_name(job_path, &read(job_path).await?)))
+    }
+
+    /// Move the job file into the archive
+    pub async fn archive(&self, job_path: &Path, success: bool) -> Result<PathBuf, Error> {
+        let destination = self.destination(job_path, success).await?;
+
+        move_file(job_path, &destination).await?;
+
+        Ok(destination)
+    }
+}
+
+/// The file name of the job file, prefixed with a timestamp and the job id so it never collides
+/// with an earlier job file of the same name
+pub fn unique_file_name(job_path: &Path, bytes: &[u8]) -> String {
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+
+    format!("{}-{}-{}", timestamp, job_id(bytes), file_name(job_path))
+}
+
+fn file_name(job_path: &Path) -> String {
+    job_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// A short id of the job, derived from the content of the job file
+pub fn job_id(bytes: &[u8]) -> String {
+    content_hash(bytes)[..12].to_owned()
+}
+
+/// Move the file, falling back to copying when renaming across file systems fails
+pub async fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if let Some(parent) = to.parent() {
+        if !parent.exists() {
+            create_dir_all(parent).await?;
+        }
+    }
+
+    match rename(from, to).await {
+        Ok(()) => {}
+        Err(error) if error.kind() == ErrorKind::NotFound => return Err(error.into()),
+        Err(_) => {
+            copy(from, to).await?;
+
+            remove_file(from).await?;
+        }
+    }
+
+    info!("moved {:?} to {:?}", from, to);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_archive() {
+        let path = PathBuf::from("./test_archive");
+
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("a.json"), "a").unwrap();
+        std::fs::write(path.join("b.json"), "b").unwrap();
+
+        let archive = Archive::new(path.clone(), false);
+
+        let destination = archive.archive(&path.join("a.json"), true).await.unwrap();
+
+        assert_eq!(destination, path.join("processed/a.json"));
+        assert!(destination.exists());
+        assert!(!path.join("a.json").exists());
+
+        let archive = Archive::new(path.clone(), true);
+
+        let destination = archive.archive(&path.join("b.json"), false).await.unwrap();
+
+        let file_name = destination.file_name().unwrap().to_string_lossy();
+
+        assert_eq!(destination.parent(), Some(path.join("failed").as_path()));
+        assert!(file_name.ends_with(&format!("-{}-b.json", job_id(b"b"))));
+        assert!(destination.exists());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,171 +1,1078 @@
-use clap::Parser;
-use dotenv::{from_filename, var};
-use globset::{Glob, GlobSet, GlobSetBuilder};
-use once_cell::sync::Lazy;
-use std::{path::PathBuf, process::exit};
-use tokio::{fs::create_dir_all, sync::OnceCell};
-use tracing::{error, info};
+use dotenvy::from_path_iter;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    env::vars,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::fs::{create_dir_all, read_to_string};
+use tracing::{info, warn};
+
+use crate::{
+    error::{ConfigError, ConfigProblem, Error},
+    file_watcher::{Backend, Trigger},
+};
+
+/// The prefix of the environment variables which override single config keys
+const ENV_PREFIX: &str = "FBR_";
+
+/// The name of the watch rule built from the top-level `LISTEN_PATH` and `WHITELIST`
+const DEFAULT_RULE: &str = "default";
+
+/// Dotfiles, editor swap files and files still being copied, unless `EXCLUDE` is set
+const DEFAULT_EXCLUDE: &[&str] = &[
+    "**/.*",
+    "**/.*/**",
+    "**/*.tmp",
+    "**/*.partial",
+    "**/*.swp",
+    "**/*~",
+];
+
+/// How often the `poll` and `hybrid` watchers scan, unless `POLL_INTERVAL_MS` is set
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+const DEFAULT_JOURNAL_RETENTION: usize = 10_000;
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    listen_path: PathBuf,
+    path: PathBuf,
+    overrides: Vec<(String, String)>,
     processor_dir_path: PathBuf,
-    globset: GlobSet,
+    rules: Vec<Arc<WatchRule>>,
+    debounce: Duration,
+    stable_period: Duration,
+    ready_marker: bool,
+    archive_rename: bool,
+    state_path: PathBuf,
+    max_concurrent_jobs: usize,
+    job_queue_size: usize,
+    journal_retention: usize,
+    grace_period: Duration,
+    watch_config: bool,
+    command_allowlist: Vec<String>,
+    command_allow_shell: bool,
+    command_env: Vec<String>,
 }
 
-#[derive(Debug, Parser)]
-#[command(author, version)]
-struct Args {
-    /// Set a custom config file
-    #[arg(short, long, value_name = "FILE")]
-    config: PathBuf,
+/// A watched directory, with the rules for the job files dropped into it
+#[derive(Debug)]
+pub struct WatchRule {
+    name: String,
+    path: PathBuf,
+    include: GlobSet,
+    exclude: GlobSet,
+    recursive: bool,
+    /// How deep below the watched directory job files are picked up, 1 for the directory itself
+    max_depth: Option<usize>,
+    triggers: Vec<Trigger>,
+    backend: Backend,
+    poll_interval: Duration,
+    processor: Option<String>,
+    max_concurrent_jobs: usize,
+    archive_path: PathBuf,
+    dead_letter_path: PathBuf,
+    result_path: Option<PathBuf>,
+    /// The directories the service writes to, never treated as job files
+    reserved: Vec<PathBuf>,
 }
 
-impl Args {
-    /// Check if the config file path is valid
-    fn validate(&self) -> bool {
-        self.config.exists() && self.config.is_file()
-    }
+/// The raw settings of the config file and its overrides, checked when the config is built
+///
+/// The keys are the dotenv keys in lowercase, e.g. `listen_path` for `LISTEN_PATH`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    listen_path: Option<PathBuf>,
+    processor_dir_path: Option<PathBuf>,
+    whitelist: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    recursive: Option<bool>,
+    max_depth: Option<usize>,
+    trigger_events: Option<Vec<Trigger>>,
+    watcher: Option<Backend>,
+    poll_interval_ms: Option<u64>,
+    debounce_ms: Option<u64>,
+    stable_period_ms: Option<u64>,
+    ready_marker: Option<bool>,
+    dead_letter_path: Option<PathBuf>,
+    archive_path: Option<PathBuf>,
+    archive_rename: Option<bool>,
+    state_path: Option<PathBuf>,
+    max_concurrent_jobs: Option<usize>,
+    job_queue_size: Option<usize>,
+    journal_retention: Option<usize>,
+    grace_period_secs: Option<u64>,
+    watch_config: Option<bool>,
+    command_allowlist: Option<Vec<String>>,
+    command_allow_shell: Option<bool>,
+    command_env: Option<Vec<String>>,
+    /// Only in TOML files, as `[[watch]]` tables
+    watch: Option<Vec<WatchSettings>>,
 }
 
-static CONFIG: Lazy<OnceCell<Config>> = Lazy::new(OnceCell::new);
+/// The raw settings of a single watch rule, unset keys fall back to the top-level ones
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WatchSettings {
+    name: Option<String>,
+    path: Option<PathBuf>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    recursive: Option<bool>,
+    max_depth: Option<usize>,
+    trigger_events: Option<Vec<Trigger>>,
+    watcher: Option<Backend>,
+    poll_interval_ms: Option<u64>,
+    /// Used for job files without a `processor_id`
+    processor: Option<String>,
+    max_concurrent_jobs: Option<usize>,
+    archive_path: Option<PathBuf>,
+    dead_letter_path: Option<PathBuf>,
+    /// Relative `result_path` and `body_path` of the jobs are resolved against it
+    result_path: Option<PathBuf>,
+}
 
-impl Config {
-    /// Get the config instance
-    pub async fn instance() -> &'static Self {
-        CONFIG
-            .get_or_init(|| {
-                let args = Args::parse();
+/// Builds a watch rule in code, for a service embedded in another program
+///
+/// Unset settings take their defaults, not the top-level keys of the config file
+#[derive(Debug, Clone)]
+pub struct WatchRuleBuilder {
+    name: String,
+    watch: WatchSettings,
+}
+
+/// The problems found so far, loading goes on with defaults so every problem is reported
+#[derive(Debug, Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Settings {
+    /// Read the config file, TOML for a `.toml` file and dotenv otherwise
+    async fn read(path: &Path, problems: &mut Problems) -> Result<Self, ConfigProblem> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            let text = read_to_string(path)
+                .await
+                .map_err(|error| ConfigProblem::Read(error.to_string()))?;
 
-                // check if the specified path exists
-                if !args.validate() {
-                    error!("The specified config file does not exist");
+            return toml::from_str(&text).map_err(|error| {
+                let line = error
+                    .span()
+                    .map_or(0, |span| text[..span.start].matches('\n').count() + 1);
 
-                    exit(1);
+                ConfigProblem::Parse(format!("line {}: {}", line, error.message()))
+            });
+        }
+
+        // unlike `from_path`, this does not touch the environment, so the file can be read again
+        let values = from_path_iter(path)
+            .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+            .map_err(|error| ConfigProblem::Read(error.to_string()))?;
+
+        let mut settings = Self::default();
+
+        // the environment takes precedence like with dotenv, other keys are not ours
+        for (key, value) in values.into_iter().chain(vars()) {
+            match settings.set(&key, &value) {
+                Ok(()) | Err(ConfigProblem::UnknownKey(_)) => {}
+                Err(problem) => problems.0.push(problem),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Apply the `FBR_*` variables of the environment, then the given overrides
+    fn apply_overrides(
+        &mut self,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(String, String)],
+        problems: &mut Problems,
+    ) {
+        for (name, value) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+
+            // the prefix may be shared with other programs, so an unknown key is not an error
+            match self.set(key, &value) {
+                Ok(()) => {}
+                Err(ConfigProblem::UnknownKey(_)) => {
+                    warn!("ignoring unknown config override {}", name);
                 }
+                Err(problem) => problems.0.push(problem),
+            }
+        }
 
-                Self::new(args.config)
-            })
-            .await
+        for (key, value) in overrides {
+            problems.check(self.set(key, value));
+        }
+    }
+
+    /// Set a single key from its string form, as in a dotenv file
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigProblem> {
+        match key.to_lowercase().as_str() {
+            "listen_path" => self.listen_path = Some(value.into()),
+            "processor_dir_path" => self.processor_dir_path = Some(value.into()),
+            "whitelist" => self.whitelist = Some(split(value)),
+            "exclude" => self.exclude = Some(split(value)),
+            "recursive" => self.recursive = Some(parse(key, value, "true or false")?),
+            "max_depth" => self.max_depth = Some(parse(key, value, "a positive number")?),
+            "trigger_events" => {
+                self.trigger_events = Some(
+                    value
+                        .split(',')
+                        .map(|trigger| parse(key, trigger, "create or rename"))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            "watcher" => self.watcher = Some(parse(key, value, "native, poll or hybrid")?),
+            "poll_interval_ms" => {
+                self.poll_interval_ms = Some(parse(key, value, "a number of milliseconds")?)
+            }
+            "debounce_ms" => {
+                self.debounce_ms = Some(parse(key, value, "a number of milliseconds")?)
+            }
+            "stable_period_ms" => {
+                self.stable_period_ms = Some(parse(key, value, "a number of milliseconds")?)
+            }
+            "ready_marker" => self.ready_marker = Some(parse(key, value, "true or false")?),
+            "dead_letter_path" => self.dead_letter_path = Some(value.into()),
+            "archive_path" => self.archive_path = Some(value.into()),
+            "archive_rename" => self.archive_rename = Some(parse(key, value, "true or false")?),
+            "state_path" => self.state_path = Some(value.into()),
+            "max_concurrent_jobs" => {
+                self.max_concurrent_jobs = Some(parse(key, value, "a positive number")?)
+            }
+            "job_queue_size" => self.job_queue_size = Some(parse(key, value, "a positive number")?),
+            "journal_retention" => {
+                self.journal_retention = Some(parse(key, value, "a positive number")?)
+            }
+            "grace_period_secs" => {
+                self.grace_period_secs = Some(parse(key, value, "a number of seconds")?)
+            }
+            "watch_config" => self.watch_config = Some(parse(key, value, "true or false")?),
+            "command_allowlist" => self.command_allowlist = Some(split(value)),
+            "command_env" => self.command_env = Some(split(value)),
+            "command_allow_shell" => {
+                self.command_allow_shell = Some(parse(key, value, "true or false")?)
+            }
+            _ => return Err(ConfigProblem::UnknownKey(key.into())),
+        }
+
+        Ok(())
+    }
+}
+
+impl Problems {
+    /// Keep the value, or record the problem and use the default instead
+    fn check<T: Default>(&mut self, result: Result<T, ConfigProblem>) -> T {
+        result.unwrap_or_else(|problem| {
+            self.0.push(problem);
+
+            T::default()
+        })
+    }
+}
+
+impl Config {
+    /// Read and validate the config file, the `FBR_*` environment variables override its keys
+    ///
+    /// Every problem in the file is reported at once
+    pub async fn load(path: PathBuf) -> Result<Self, Error> {
+        Self::load_with_overrides(path, Vec::new()).await
     }
 
-    async fn new(config: PathBuf) -> Self {
+    /// Like `load`, with `KEY=VALUE` overrides which take precedence over everything else
+    pub async fn load_with_overrides(
+        path: PathBuf,
+        overrides: Vec<(String, String)>,
+    ) -> Result<Self, Error> {
         info!("Reading config file...");
 
-        // load the config file
-        from_filename(&config).ok();
+        let mut problems = Problems::default();
 
-        let listen_path = Self::get_path_from_env("LISTEN_PATH").await;
+        let mut settings = match Settings::read(&path, &mut problems).await {
+            Ok(settings) => settings,
+            Err(problem) => {
+                return Err(ConfigError {
+                    path,
+                    problems: vec![problem],
+                }
+                .into())
+            }
+        };
 
-        let processor_dir_path = Self::get_path_from_env("PROCESSOR_DIR_PATH").await;
+        settings.apply_overrides(vars(), &overrides, &mut problems);
 
-        let globset = Self::build_globset();
+        let processor_dir_path = problems.check(Self::required_dir(
+            settings.processor_dir_path.take(),
+            "PROCESSOR_DIR_PATH",
+        ));
 
-        Self {
-            listen_path,
+        let debounce =
+            Duration::from_millis(or_default(settings.debounce_ms, "DEBOUNCE_MS", 1, "1ms"));
+
+        let stable_period = Duration::from_millis(or_default(
+            settings.stable_period_ms,
+            "STABLE_PERIOD_MS",
+            500,
+            "500ms",
+        ));
+
+        let ready_marker = settings.ready_marker.unwrap_or(false);
+
+        let archive_rename = settings.archive_rename.unwrap_or(false);
+
+        let max_concurrent_jobs = problems.check(positive(
+            "MAX_CONCURRENT_JOBS",
+            or_default(settings.max_concurrent_jobs, "MAX_CONCURRENT_JOBS", 4, "4"),
+        ));
+
+        let job_queue_size = problems.check(positive(
+            "JOB_QUEUE_SIZE",
+            or_default(settings.job_queue_size, "JOB_QUEUE_SIZE", 100, "100"),
+        ));
+
+        let journal_retention = problems.check(positive(
+            "JOURNAL_RETENTION",
+            settings
+                .journal_retention
+                .unwrap_or(DEFAULT_JOURNAL_RETENTION),
+        ));
+
+        let grace_period = Duration::from_secs(or_default(
+            settings.grace_period_secs,
+            "GRACE_PERIOD_SECS",
+            30,
+            "30s",
+        ));
+
+        let watch_config = settings.watch_config.unwrap_or(false);
+
+        // no command may run unless it is listed
+        let command_allowlist = settings.command_allowlist.take().unwrap_or_default();
+
+        // a shell runs any program, so it is never allowed by the allowlist alone
+        let command_allow_shell = settings.command_allow_shell.unwrap_or(false);
+
+        // the commands start with an empty environment, apart from these
+        let command_env = settings.command_env.take().unwrap_or_default();
+
+        let mut watches = settings.watch.take().unwrap_or_default();
+
+        // the top-level keys make up a rule of their own, which is the only one in a dotenv file
+        if settings.listen_path.is_some() || watches.is_empty() {
+            watches.insert(
+                0,
+                WatchSettings {
+                    name: Some(DEFAULT_RULE.into()),
+                    path: settings.listen_path.take(),
+                    include: Some(or_default(
+                        settings.whitelist.take(),
+                        "WHITELIST",
+                        vec![],
+                        "blank whitelist",
+                    )),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let first_path = match &watches[0].path {
+            Some(path) => problems.check(Self::check_dir(path.clone())),
+            None => PathBuf::new(),
+        };
+
+        // the job journal lives here
+        let state_path = problems.check(Self::optional_dir(
+            settings.state_path.take(),
+            &first_path,
+            ".state",
+        ));
+
+        let mut rules = Vec::with_capacity(watches.len());
+        let mut names = HashSet::new();
+
+        for watch in watches {
+            let name = watch
+                .name
+                .clone()
+                .or_else(|| {
+                    watch
+                        .path
+                        .as_ref()
+                        .map(|path| path.to_string_lossy().into())
+                })
+                .unwrap_or_default();
+
+            if !names.insert(name.clone()) {
+                problems
+                    .0
+                    .push(invalid_value("watch.name", "a unique name", name.clone()));
+            }
+
+            let mut rule_problems = Problems::default();
+
+            let rule = WatchRule::build(
+                watch,
+                name.clone(),
+                &settings,
+                max_concurrent_jobs,
+                &state_path,
+                &mut rule_problems,
+            );
+
+            // the problems of the top-level rule are reported with the top-level keys
+            if name == DEFAULT_RULE {
+                problems.0.extend(rule_problems.0);
+            } else {
+                problems.0.extend(
+                    rule_problems
+                        .0
+                        .into_iter()
+                        .map(|problem| ConfigProblem::Rule {
+                            name: name.clone(),
+                            problem: Box::new(problem),
+                        }),
+                );
+            }
+
+            rules.push(Arc::new(rule));
+        }
+
+        // nothing is created for a config which is rejected anyway
+        if problems.0.is_empty() {
+            let dirs = [processor_dir_path.as_path(), state_path.as_path()]
+                .into_iter()
+                .chain(rules.iter().flat_map(|rule| rule.dirs()));
+
+            Self::create_dirs(dirs, &mut problems).await;
+        }
+
+        if !problems.0.is_empty() {
+            return Err(ConfigError {
+                path,
+                problems: problems.0,
+            }
+            .into());
+        }
+
+        Ok(Self {
+            path,
+            overrides,
             processor_dir_path,
-            globset,
+            rules,
+            debounce,
+            stable_period,
+            ready_marker,
+            archive_rename,
+            state_path,
+            max_concurrent_jobs,
+            job_queue_size,
+            journal_retention,
+            grace_period,
+            watch_config,
+            command_allowlist,
+            command_allow_shell,
+            command_env,
+        })
+    }
+
+    /// Add watch rules built in code, next to the rules of the config file
+    pub async fn with_rules(mut self, rules: &[WatchRuleBuilder]) -> Result<Self, Error> {
+        let mut problems = Problems::default();
+
+        for rule in rules {
+            if self.rules.iter().any(|existing| existing.name == rule.name) {
+                problems.0.push(invalid_value(
+                    "watch.name",
+                    "a unique name",
+                    rule.name.clone(),
+                ));
+            }
+
+            let mut rule_problems = Problems::default();
+
+            let built = WatchRule::build(
+                rule.watch.clone(),
+                rule.name.clone(),
+                &Settings::default(),
+                self.max_concurrent_jobs,
+                &self.state_path,
+                &mut rule_problems,
+            );
+
+            problems.0.extend(
+                rule_problems
+                    .0
+                    .into_iter()
+                    .map(|problem| ConfigProblem::Rule {
+                        name: rule.name.clone(),
+                        problem: Box::new(problem),
+                    }),
+            );
+
+            self.rules.push(Arc::new(built));
         }
+
+        if problems.0.is_empty() {
+            let added = &self.rules[self.rules.len() - rules.len()..];
+
+            Self::create_dirs(added.iter().flat_map(|rule| rule.dirs()), &mut problems).await;
+        }
+
+        if !problems.0.is_empty() {
+            return Err(ConfigError {
+                path: self.path,
+                problems: problems.0,
+            }
+            .into());
+        }
+
+        Ok(self)
+    }
+
+    /// Load the config file again, with the same overrides
+    pub async fn reload(&self) -> Result<Self, Error> {
+        Self::load_with_overrides(self.path.clone(), self.overrides.clone()).await
+    }
+
+    /// The config file this config was loaded from
+    pub fn path(&self) -> &PathBuf {
+        &self.path
     }
 
-    pub fn listen_path(&self) -> &PathBuf {
-        &self.listen_path
+    /// The `KEY=VALUE` overrides this config was loaded with
+    pub fn overrides(&self) -> &[(String, String)] {
+        &self.overrides
     }
 
     pub fn processor_dir_path(&self) -> &PathBuf {
         &self.processor_dir_path
     }
 
-    pub fn globset(&self) -> &GlobSet {
-        &self.globset
+    /// The watch rules, the top-level rule comes first
+    pub fn rules(&self) -> &[Arc<WatchRule>] {
+        &self.rules
     }
 
-    /// Get the PathBuff with the given name from the environment
-    async fn get_path_from_env(name: &str) -> PathBuf {
-        let path_string = match var(name) {
-            Ok(path_string) => path_string,
-            Err(_) => {
-                error!("Did not find {} in config file", name);
+    /// The rule of the directory the job file is in, the most specific one if several contain it
+    pub fn rule_for(&self, path: &Path) -> Option<&Arc<WatchRule>> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.contains(path))
+            .max_by_key(|rule| rule.path.components().count())
+    }
 
-                exit(1);
-            }
+    pub fn debounce(&self) -> Duration {
+        self.debounce
+    }
+
+    /// How long a job file must stay unchanged before it is read, zero to read it immediately
+    pub fn stable_period(&self) -> Duration {
+        self.stable_period
+    }
+
+    /// Whether job files wait for a `.ready` marker before being processed
+    pub fn ready_marker(&self) -> bool {
+        self.ready_marker
+    }
+
+    /// Whether archived job files are prefixed with a timestamp and the job id
+    pub fn archive_rename(&self) -> bool {
+        self.archive_rename
+    }
+
+    pub fn state_path(&self) -> &PathBuf {
+        &self.state_path
+    }
+
+    pub fn max_concurrent_jobs(&self) -> usize {
+        self.max_concurrent_jobs
+    }
+
+    pub fn job_queue_size(&self) -> usize {
+        self.job_queue_size
+    }
+
+    /// How many finished jobs the journal remembers, so an identical copy of one is not run again
+    pub fn journal_retention(&self) -> usize {
+        self.journal_retention
+    }
+
+    /// How long running jobs may take to finish when the service shuts down
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Whether the config file is reloaded when it changes, besides on SIGHUP
+    pub fn watch_config(&self) -> bool {
+        self.watch_config
+    }
+
+    /// The programs the `com.proxy.command.exec` jobs may run
+    pub fn command_allowlist(&self) -> &[String] {
+        &self.command_allowlist
+    }
+
+    /// Whether the `com.proxy.command.exec` jobs may run a command line in a shell
+    pub fn command_allow_shell(&self) -> bool {
+        self.command_allow_shell
+    }
+
+    /// The variables the `com.proxy.command.exec` programs get from the service, or from the job
+    pub fn command_env(&self) -> &[String] {
+        &self.command_env
+    }
+
+    /// The directory with the given name, which must be set
+    fn required_dir(path: Option<PathBuf>, name: &str) -> Result<PathBuf, ConfigProblem> {
+        let Some(path) = path else {
+            return Err(ConfigProblem::MissingKey(name.into()));
         };
 
-        let path = PathBuf::from(path_string);
+        Self::check_dir(path)
+    }
+
+    /// The given directory, or the default directory under the listen path
+    fn optional_dir(
+        path: Option<PathBuf>,
+        listen_path: &Path,
+        default: &str,
+    ) -> Result<PathBuf, ConfigProblem> {
+        if let Some(path) = path {
+            return Self::check_dir(path);
+        }
+
+        // a missing listen path is reported already, there is nothing to derive from
+        if listen_path.as_os_str().is_empty() {
+            return Ok(PathBuf::new());
+        }
+
+        Self::check_dir(listen_path.join(default))
+    }
+
+    /// The directory, which is created once the whole config is valid unless it exists already
+    fn check_dir(path: PathBuf) -> Result<PathBuf, ConfigProblem> {
+        if path.exists() && !path.is_dir() {
+            return Err(ConfigProblem::NotDirectory(path));
+        }
+
+        Ok(path)
+    }
+
+    /// Create the directories which do not exist, recording the ones which fail
+    async fn create_dirs<'a>(dirs: impl Iterator<Item = &'a Path>, problems: &mut Problems) {
+        for path in dirs {
+            if path.exists() {
+                continue;
+            }
 
-        if !path.exists() {
             info!(
                 "The path {} does not exist, creating it...",
                 path.to_string_lossy()
             );
 
-            if let Err(error) = create_dir_all(&path).await {
-                error!(
-                    "Failed to create path {}: {}",
-                    path.to_string_lossy(),
-                    error
-                );
+            if let Err(source) = create_dir_all(path).await {
+                problems.0.push(ConfigProblem::CreateDir {
+                    path: path.to_owned(),
+                    source,
+                });
 
-                exit(1);
+                continue;
             }
 
             info!("Successfully created path {}", path.to_string_lossy());
         }
+    }
+
+    /// Construct a new globset from the given patterns, recording every invalid pattern
+    ///
+    /// The patterns match paths relative to the watched directory, `*` never matches a `/`
+    fn build_globset(patterns: Vec<String>, problems: &mut Problems) -> GlobSet {
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let glob = match GlobBuilder::new(&pattern).literal_separator(true).build() {
+                Ok(glob) => glob,
+                Err(error) => {
+                    problems.0.push(ConfigProblem::InvalidGlob {
+                        pattern,
+                        message: error.kind().to_string(),
+                    });
+
+                    continue;
+                }
+            };
 
-        if !path.is_dir() {
-            error!("The path {} is not a directory", path.to_string_lossy());
+            builder.add(glob);
 
-            exit(1);
+            info!("Added pattern: {}", pattern);
         }
 
-        path
+        problems.check(builder.build().map_err(|error| ConfigProblem::InvalidGlob {
+            pattern: "*".into(),
+            message: error.to_string(),
+        }))
     }
+}
 
-    /// Construct a new globset from the given whitelist
-    fn build_globset() -> GlobSet {
-        let whitelist = match var("WHITELIST") {
-            Ok(whitelist) => {
-                if whitelist.is_empty() {
-                    vec![]
-                } else {
-                    whitelist
-                        .split(',')
-                        .map(|s| s.into())
-                        .collect::<Vec<String>>()
-                }
+impl WatchRule {
+    /// Start building a rule which watches the given directory
+    pub fn builder(name: impl Into<String>, path: impl Into<PathBuf>) -> WatchRuleBuilder {
+        WatchRuleBuilder {
+            name: name.into(),
+            watch: WatchSettings {
+                path: Some(path.into()),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Build the rule, recording its problems
+    fn build(
+        watch: WatchSettings,
+        name: String,
+        defaults: &Settings,
+        max_concurrent_jobs: usize,
+        state_path: &Path,
+        problems: &mut Problems,
+    ) -> Self {
+        let path_key = match name.as_str() {
+            DEFAULT_RULE => "LISTEN_PATH",
+            _ => "path",
+        };
+
+        let path = problems.check(Config::required_dir(watch.path, path_key));
+
+        let include = match watch.include {
+            Some(include) => Config::build_globset(include, problems),
+            None => {
+                problems.0.push(ConfigProblem::MissingKey("include".into()));
+
+                GlobSet::empty()
             }
-            Err(_) => {
-                info!("Did not find WHITELIST in config file, using blank whitelist");
-                vec![]
+        };
+
+        let exclude = watch
+            .exclude
+            .or_else(|| defaults.exclude.clone())
+            .unwrap_or_else(|| {
+                DEFAULT_EXCLUDE
+                    .iter()
+                    .map(|&pattern| pattern.into())
+                    .collect()
+            });
+
+        let exclude = Config::build_globset(exclude, problems);
+
+        let recursive = watch.recursive.or(defaults.recursive).unwrap_or(false);
+
+        let max_depth = watch
+            .max_depth
+            .or(defaults.max_depth)
+            .map(|max_depth| problems.check(positive("max_depth", max_depth)));
+
+        let triggers = or_default(
+            watch
+                .trigger_events
+                .or_else(|| defaults.trigger_events.clone()),
+            "TRIGGER_EVENTS",
+            vec![Trigger::Create, Trigger::Rename],
+            "create,rename",
+        );
+
+        let backend = watch.watcher.or(defaults.watcher).unwrap_or_default();
+
+        let poll_interval = match watch.poll_interval_ms.or(defaults.poll_interval_ms) {
+            Some(0) => {
+                problems.0.push(invalid_value(
+                    "poll_interval_ms",
+                    "a positive number of milliseconds",
+                    "0",
+                ));
+
+                DEFAULT_POLL_INTERVAL
             }
+            Some(poll_interval_ms) => Duration::from_millis(poll_interval_ms),
+            None => DEFAULT_POLL_INTERVAL,
         };
 
-        let mut builder = GlobSetBuilder::new();
+        let max_concurrent_jobs = match watch.max_concurrent_jobs {
+            Some(value) => problems.check(positive("max_concurrent_jobs", value)),
+            None => max_concurrent_jobs,
+        };
 
-        for pattern in whitelist {
-            let glob = match Glob::new(&pattern) {
-                Ok(glob) => glob,
-                Err(_) => {
-                    error!("Failed to parse whitelist pattern: {}", pattern);
+        // handled jobs are moved to `processed/` and `failed/` under this path
+        let archive_path = match watch.archive_path.or_else(|| defaults.archive_path.clone()) {
+            Some(archive_path) => problems.check(Config::check_dir(archive_path)),
+            None => path.clone(),
+        };
 
-                    exit(1);
-                }
-            };
+        // failed jobs without a known result path end up here
+        let dead_letter_path = problems.check(Config::optional_dir(
+            watch
+                .dead_letter_path
+                .or_else(|| defaults.dead_letter_path.clone()),
+            &path,
+            ".dead-letter",
+        ));
 
-            builder.add(glob);
+        let result_path = watch
+            .result_path
+            .map(|result_path| problems.check(Config::check_dir(result_path)));
 
-            info!("Added whitelist pattern: {}", pattern);
+        let mut reserved = vec![
+            archive_path.join("processed"),
+            archive_path.join("failed"),
+            dead_letter_path.clone(),
+            state_path.to_owned(),
+        ];
+
+        reserved.extend(result_path.clone());
+
+        Self {
+            name,
+            path,
+            include,
+            exclude,
+            recursive,
+            max_depth,
+            triggers,
+            backend,
+            poll_interval,
+            processor: watch.processor,
+            max_concurrent_jobs,
+            archive_path,
+            dead_letter_path,
+            result_path,
+            reserved,
         }
+    }
 
-        match builder.build() {
-            Ok(set) => set,
-            Err(_) => {
-                error!("Failed to build whitelist glob set");
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-                exit(1);
-            }
+    /// The directories the rule needs before it is watched
+    fn dirs(&self) -> impl Iterator<Item = &Path> {
+        [&self.path, &self.archive_path, &self.dead_letter_path]
+            .into_iter()
+            .chain(&self.result_path)
+            .map(PathBuf::as_path)
+    }
+
+    /// The watched directory
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Whether the subdirectories are watched too
+    pub fn recursive(&self) -> bool {
+        self.recursive
+    }
+
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    /// How changes in the watched directory are noticed
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// How often the `poll` and `hybrid` watchers scan the watched directory
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// The processor of job files without a `processor_id`
+    pub fn processor(&self) -> Option<&str> {
+        self.processor.as_deref()
+    }
+
+    /// How many jobs of this rule run at the same time, within the global limit
+    pub fn max_concurrent_jobs(&self) -> usize {
+        self.max_concurrent_jobs
+    }
+
+    pub fn archive_path(&self) -> &PathBuf {
+        &self.archive_path
+    }
+
+    pub fn dead_letter_path(&self) -> &PathBuf {
+        &self.dead_letter_path
+    }
+
+    /// The directory relative result paths of the jobs are resolved against
+    pub fn result_path(&self) -> Option<&PathBuf> {
+        self.result_path.as_ref()
+    }
+
+    /// How deep below the watched directory job files are picked up, 1 for the directory itself
+    pub fn max_depth(&self) -> Option<usize> {
+        if self.recursive {
+            self.max_depth
+        } else {
+            Some(1)
         }
     }
+
+    /// Whether the path is in the watched directory, or below it within the max depth
+    pub fn contains(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.path) else {
+            return false;
+        };
+
+        let depth = relative.components().count();
+
+        depth > 0 && self.max_depth().is_none_or(|max_depth| depth <= max_depth)
+    }
+
+    /// Whether the file is a job file of this rule, the globs match the path relative to the rule
+    pub fn matches(&self, path: &Path) -> bool {
+        if !self.contains(path)
+            || self
+                .reserved
+                .iter()
+                .any(|reserved| path.starts_with(reserved))
+        {
+            return false;
+        }
+
+        // `contains` checked the prefix already
+        let relative = path.strip_prefix(&self.path).unwrap_or(path);
+
+        self.include.is_match(relative) && !self.exclude.is_match(relative)
+    }
+}
+
+/// Use the value, or log and use the default
+fn or_default<T>(value: Option<T>, name: &str, default: T, description: impl Display) -> T {
+    value.unwrap_or_else(|| {
+        info!(
+            "Did not find {} in config file, using {}",
+            name, description
+        );
+
+        default
+    })
+}
+
+impl WatchRuleBuilder {
+    /// The globs of the job files, relative to the watched directory
+    pub fn include<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.watch.include = Some(patterns.into_iter().map(Into::into).collect());
+
+        self
+    }
+
+    /// The globs of the files which are never job files, instead of the default ones
+    pub fn exclude<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.watch.exclude = Some(patterns.into_iter().map(Into::into).collect());
+
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.watch.recursive = Some(recursive);
+
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.watch.max_depth = Some(max_depth);
+
+        self
+    }
+
+    pub fn triggers(mut self, triggers: impl IntoIterator<Item = Trigger>) -> Self {
+        self.watch.trigger_events = Some(triggers.into_iter().collect());
+
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.watch.watcher = Some(backend);
+
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.watch.poll_interval_ms = Some(poll_interval.as_millis() as u64);
+
+        self
+    }
+
+    /// The processor of job files without a `processor_id`
+    pub fn processor(mut self, processor: impl Into<String>) -> Self {
+        self.watch.processor = Some(processor.into());
+
+        self
+    }
+
+    pub fn max_concurrent_jobs(mut self, max_concurrent_jobs: usize) -> Self {
+        self.watch.max_concurrent_jobs = Some(max_concurrent_jobs);
+
+        self
+    }
+
+    pub fn archive_path(mut self, archive_path: impl Into<PathBuf>) -> Self {
+        self.watch.archive_path = Some(archive_path.into());
+
+        self
+    }
+
+    pub fn dead_letter_path(mut self, dead_letter_path: impl Into<PathBuf>) -> Self {
+        self.watch.dead_letter_path = Some(dead_letter_path.into());
+
+        self
+    }
+
+    pub fn result_path(mut self, result_path: impl Into<PathBuf>) -> Self {
+        self.watch.result_path = Some(result_path.into());
+
+        self
+    }
+}
+
+/// Split a comma separated list, an empty string is an empty list
+fn split(value: &str) -> Vec<String> {
+    if value.is_empty() {
+        vec![]
+    } else {
+        value.split(',').map(|s| s.into()).collect()
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str, expected: &'static str) -> Result<T, ConfigProblem> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_value(key, expected, value))
+}
+
+fn positive(name: &str, value: usize) -> Result<usize, ConfigProblem> {
+    match value {
+        0 => Err(invalid_value(name, "a positive number", "0")),
+        value => Ok(value),
+    }
+}
+
+fn invalid_value(key: &str, expected: &'static str, value: impl Into<String>) -> ConfigProblem {
+    ConfigProblem::InvalidValue {
+        key: key.into(),
+        expected,
+        value: value.into(),
+    }
 }
 
 #[cfg(test)]
@@ -175,7 +1082,7 @@ mod tests {
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     use std::env::current_dir;
     #[cfg(any(target_os = "linux", target_os = "macos"))]
-    use tokio::fs::write;
+    use tokio::fs::{remove_file, write};
 
     // Only used in linux os or mac os
     #[cfg(any(target_os = "linux", target_os = "macos"))]
@@ -190,6 +1097,10 @@ mod tests {
             LISTEN_PATH="{}"
             PROCESSOR_DIR_PATH="{}"
             WHITELIST="*.txt"
+            TRIGGER_EVENTS="create"
+            STABLE_PERIOD_MS=0
+            READY_MARKER=true
+            MAX_CONCURRENT_JOBS=8
         "#,
             listen_path.to_string_lossy(),
             processor_dir_path.to_string_lossy()
@@ -207,10 +1118,16 @@ mod tests {
         let current_dir = current_dir().unwrap();
         let config_path = current_dir.join("test_config.env");
 
-        let config = Config::new(config_path).await;
+        let config = Config::load(config_path).await.unwrap();
+
+        assert_eq!(config.rules().len(), 1);
+
+        let rule = &config.rules()[0];
+
+        assert_eq!(rule.name(), "default");
 
         assert_eq!(
-            config.listen_path().to_string_lossy(),
+            rule.path().to_string_lossy(),
             current_dir.join("test/listen").to_string_lossy()
         );
 
@@ -219,6 +1136,279 @@ mod tests {
             current_dir.join("test/processor").to_string_lossy()
         );
 
-        assert_eq!(config.globset().len(), 1);
+        assert!(rule.matches(&current_dir.join("test/listen/job.txt")));
+
+        assert!(!rule.matches(&current_dir.join("test/listen/job.json")));
+
+        assert_eq!(rule.triggers(), &[Trigger::Create]);
+
+        assert_eq!(config.debounce(), Duration::from_millis(1));
+
+        assert!(config.stable_period().is_zero());
+
+        assert!(config.ready_marker());
+
+        assert_eq!(
+            rule.dead_letter_path().to_string_lossy(),
+            current_dir
+                .join("test/listen/.dead-letter")
+                .to_string_lossy()
+        );
+
+        assert_eq!(config.max_concurrent_jobs(), 8);
+
+        assert_eq!(config.job_queue_size(), 100);
+
+        assert_eq!(config.journal_retention(), 10_000);
+
+        assert_eq!(config.grace_period(), Duration::from_secs(30));
+
+        let csv = WatchRule::builder("csv", current_dir.join("test/listen/csv"))
+            .include(["*.csv"])
+            .processor("com.proxy.network.io");
+
+        let config = config.with_rules(&[csv]).await.unwrap();
+
+        let job = current_dir.join("test/listen/csv/job.csv");
+
+        assert_eq!(config.rule_for(&job).map(|rule| rule.name()), Some("csv"));
+
+        assert!(config.rules()[1].matches(&job));
+
+        // the name is taken by the rule of the config file
+        let default = WatchRule::builder("default", current_dir.join("test/other")).include(["*"]);
+
+        assert!(config.with_rules(&[default]).await.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos",))]
+    async fn test_config_problems() {
+        let current_dir = current_dir().unwrap();
+        let config_path = current_dir.join("test_config_problems.env");
+
+        write(
+            &config_path,
+            r#"
+            WHITELIST="*.json,a[b"
+            STABLE_PERIOD_MS=soon
+            MAX_CONCURRENT_JOBS=0
+        "#,
+        )
+        .await
+        .unwrap();
+
+        let error = Config::load(config_path.clone()).await.unwrap_err();
+
+        remove_file(&config_path).await.unwrap();
+
+        let Error::Config(error) = error else {
+            panic!("unexpected error: {}", error);
+        };
+
+        let problems = error
+            .problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            problems,
+            [
+                "STABLE_PERIOD_MS must be a number of milliseconds, got soon",
+                "did not find PROCESSOR_DIR_PATH in config file",
+                "MAX_CONCURRENT_JOBS must be a positive number, got 0",
+                "did not find LISTEN_PATH in config file",
+                "failed to parse whitelist pattern a[b: unclosed character class; missing ']'",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos",))]
+    async fn test_toml_config() {
+        let current_dir = current_dir().unwrap();
+        let config_path = current_dir.join("test_config.toml");
+
+        let config = format!(
+            r#"
+            listen_path = "{}"
+            processor_dir_path = "{}"
+            whitelist = ["*.json", "*.txt"]
+            trigger_events = ["rename"]
+            stable_period_ms = 0
+            max_concurrent_jobs = 2
+            watcher = "hybrid"
+            command_allowlist = ["convert"]
+            command_allow_shell = true
+            command_env = ["PATH", "HOME"]
+
+            [[watch]]
+            name = "reports"
+            path = "{}"
+            include = ["**/*.json"]
+            exclude = ["draft-*"]
+            recursive = true
+            max_depth = 2
+            watcher = "poll"
+            poll_interval_ms = 500
+            processor = "com.proxy.network.io"
+            max_concurrent_jobs = 1
+        "#,
+            current_dir.join("test/toml/listen").to_string_lossy(),
+            current_dir.join("test/toml/processor").to_string_lossy(),
+            current_dir
+                .join("test/toml/listen/reports")
+                .to_string_lossy()
+        );
+
+        write(&config_path, config).await.unwrap();
+
+        let overrides = vec![("MAX_CONCURRENT_JOBS".into(), "6".into())];
+
+        let config = Config::load_with_overrides(config_path.clone(), overrides)
+            .await
+            .unwrap();
+
+        remove_file(&config_path).await.unwrap();
+
+        let [default, reports] = config.rules() else {
+            panic!("unexpected rules: {:?}", config.rules());
+        };
+
+        assert_eq!(default.path(), &current_dir.join("test/toml/listen"));
+
+        assert_eq!(default.triggers(), &[Trigger::Rename]);
+
+        // the top-level trigger events are the default of every rule
+        assert_eq!(reports.triggers(), &[Trigger::Rename]);
+
+        assert_eq!(default.backend(), Backend::Hybrid);
+
+        assert_eq!(default.poll_interval(), Duration::from_secs(2));
+
+        assert_eq!(reports.backend(), Backend::Poll);
+
+        assert_eq!(reports.poll_interval(), Duration::from_millis(500));
+
+        assert_eq!(reports.processor(), Some("com.proxy.network.io"));
+
+        assert_eq!(reports.max_concurrent_jobs(), 1);
+
+        assert_eq!(config.command_allowlist(), &["convert"]);
+
+        assert!(config.command_allow_shell());
+
+        assert_eq!(config.command_env(), &["PATH", "HOME"]);
+
+        let job = current_dir.join("test/toml/listen/reports/2024/job.json");
+
+        assert!(reports.matches(&job));
+
+        // the default excludes, the rule has its own
+        assert!(!default.matches(&current_dir.join("test/toml/listen/.job.json")));
+
+        assert!(reports.matches(&current_dir.join("test/toml/listen/reports/.job.json")));
+
+        // deeper than the max depth
+        assert!(!reports.matches(&current_dir.join("test/toml/listen/reports/2024/01/job.json")));
+
+        // `*` does not match a separator, so the top-level rule only takes its own files
+        assert!(!default.matches(&current_dir.join("test/toml/listen/2024/job.json")));
+
+        assert!(!reports.matches(&current_dir.join("test/toml/listen/reports/draft-job.json")));
+
+        // archived job files are never jobs again
+        assert!(!reports.matches(&current_dir.join("test/toml/listen/reports/processed/job.json")));
+
+        assert_eq!(
+            config.rule_for(&job).map(|rule| rule.name()),
+            Some("reports")
+        );
+
+        assert_eq!(
+            config
+                .rule_for(&current_dir.join("test/toml/listen/job.json"))
+                .map(|rule| rule.name()),
+            Some("default")
+        );
+
+        assert!(config.stable_period().is_zero());
+
+        // the override wins over the file
+        assert_eq!(config.max_concurrent_jobs(), 6);
+
+        assert_eq!(config.job_queue_size(), 100);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos",))]
+    fn test_overrides() {
+        let env = [
+            ("FBR_MAX_DEPTH", "2"),
+            ("FBR_DEBOUNCE_MS", "5"),
+            // not one of ours, only warned about
+            ("FBR_UNRELATED_SETTING", "1"),
+            ("MAX_DEPTH", "9"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+
+        let mut settings = Settings::default();
+        let mut problems = Problems::default();
+
+        settings.apply_overrides(env, &[("DEBOUNCE_MS".into(), "7".into())], &mut problems);
+
+        assert!(problems.0.is_empty(), "{:?}", problems);
+
+        assert_eq!(settings.max_depth, Some(2));
+
+        // the explicit overrides come last
+        assert_eq!(settings.debounce_ms, Some(7));
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos",))]
+    async fn test_toml_config_problems() {
+        let current_dir = current_dir().unwrap();
+        let config_path = current_dir.join("test_config_problems.toml");
+
+        let listen_path = current_dir.join("test/rejected/listen");
+
+        write(
+            &config_path,
+            format!(
+                "listen_path = {:?}\nprocessor_dir_path = {:?}\nmax_concurrent_jobs = 0\n",
+                listen_path,
+                current_dir.join("test/rejected/processor")
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert!(Config::load(config_path.clone()).await.is_err());
+
+        // nothing is created for a rejected config
+        assert!(!current_dir.join("test/rejected").exists());
+
+        write(
+            &config_path,
+            "listen_path = \"listen\"\n\nmax_depth = \"deep\"\n",
+        )
+        .await
+        .unwrap();
+
+        let error = Config::load(config_path.clone()).await.unwrap_err();
+
+        remove_file(&config_path).await.unwrap();
+
+        let Error::Config(error) = error else {
+            panic!("unexpected error: {}", error);
+        };
+
+        assert!(
+            error.problems[0].to_string().contains("line 3:"),
+            "{}",
+            error.problems[0]
+        );
     }
 }
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{error::Category as JsonCategory, to_vec_pretty};
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read, write};
+use tracing::warn;
+
+use crate::{
+    archive::unique_file_name,
+    error::Error::{self, *},
+};
+
+/// The sidecar written next to a dead job file, describing why it failed
+#[derive(Debug, Serialize)]
+struct Envelope<'a> {
+    category: Category,
+    message: String,
+    /// Where the job file was submitted
+    original_path: &'a Path,
+    /// The byte offset of a JSON error in the job file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// The job file is not valid JSON
+    Parse,
+    /// The job file is valid JSON, but not a valid job
+    Validation,
+    ProcessorNotFound,
+    Network,
+    Io,
+}
+
+impl Category {
+    pub fn of(error: &Error) -> Self {
+        match error {
+            SerdeJson(error) => match error.classify() {
+                JsonCategory::Syntax | JsonCategory::Eof => Category::Parse,
+                JsonCategory::Data => Category::Validation,
+                JsonCategory::Io => Category::Io,
+            },
+            InvalidMethod(_)
+            | UrlParse(_)
+            | InvalidHeaderName(_)
+            | InvalidHeaderValue(_)
+            | InvalidStatusCode(_)
+            | CommandNotAllowed(_)
+            | EnvNotAllowed(_)
+            | InvalidJob(_)
+            | EndpointNotFound(_) => Category::Validation,
+            ProcessorNotFound(_) => Category::ProcessorNotFound,
+            Reqwest(_) | RequestAttempts { .. } => Category::Network,
+            _ => Category::Io,
+        }
+    }
+}
+
+/// Copy the job file into the dead-letter directory, along with a `.error.json` sidecar
+///
+/// Both are prefixed with a timestamp and the job id, like the renamed archived files, so a job
+/// file of the same name never replaces them. The job file itself is left to the archive. Return the path of the sidecar. A dead job can be
+/// replayed by moving the copy back to the listen path
+pub async fn write_dead_letter(
+    dead_letter_path: &Path,
+    job_path: &Path,
+    error: &Error,
+) -> Result<PathBuf, Error> {
+    if !dead_letter_path.exists() {
+        create_dir_all(dead_letter_path).await?;
+    }
+
+    let (line, column) = match error {
+        SerdeJson(error) if error.line() > 0 => (Some(error.line()), Some(error.column())),
+        _ => (None, None),
+    };
+
+    // the job file may be gone already, e.g. deleted before it could be read
+    let bytes = if job_path.exists() {
+        Some(read(job_path).await?)
+    } else {
+        None
+    };
+
+    let offset = match (line, column, &bytes) {
+        (Some(line), Some(column), Some(bytes)) => Some(byte_offset(bytes, line, column)),
+        _ => None,
+    };
+
+    let envelope = Envelope {
+        category: Category::of(error),
+        message: error.to_string(),
+        original_path: job_path,
+        offset,
+        line,
+        column,
+        timestamp: Utc::now(),
+    };
+
+    let file_name = unique_file_name(job_path, bytes.as_deref().unwrap_or_default());
+
+    let record_path = dead_letter_path.join(format!("{}.error.json", file_name));
+
+    write(&record_path, to_vec_pretty(&envelope)?).await?;
+
+    if let Some(bytes) = &bytes {
+        write(dead_letter_path.join(&file_name), bytes).await?;
+    }
+
+    warn!("dead letter written: {:?}", record_path);
+
+    Ok(record_path)
+}
+
+/// Turn the one-based line and column reported by serde_json into a byte offset
+fn byte_offset(bytes: &[u8], line: usize, column: usize) -> usize {
+    let line_start = bytes
+        .split_inclusive(|byte| *byte == b'\n')
+        .take(line - 1)
+        .map(<[u8]>::len)
+        .sum::<usize>();
+
+    (line_start + column.saturating_sub(1)).min(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::job_id;
+    use serde_json::{from_slice, Value};
+
+    #[tokio::test]
+    async fn test_write_dead_letter() {
+        let path = PathBuf::from("./test_dead_letter");
+        let dead_letter_path = path.join("dead-letter");
+        let job_path = path.join("job.json");
+
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(&job_path, "{\n  \"processor_id\": oops\n}").unwrap();
+
+        let error = from_slice::<Value>(&std::fs::read(&job_path).unwrap()).unwrap_err();
+
+        let record_path = write_dead_letter(&dead_letter_path, &job_path, &SerdeJson(error))
+            .await
+            .unwrap();
+
+        let file_name = record_path.file_name().unwrap().to_string_lossy();
+
+        let job_id = job_id(&std::fs::read(&job_path).unwrap());
+
+        assert!(file_name.ends_with(&format!("-{}-job.json.error.json", job_id)));
+
+        let copy_path = dead_letter_path.join(file_name.trim_end_matches(".error.json"));
+
+        assert_eq!(
+            std::fs::read(&copy_path).unwrap(),
+            std::fs::read(&job_path).unwrap()
+        );
+
+        // left to the archive
+        assert!(job_path.exists());
+
+        let record: Value = from_slice(&std::fs::read(&record_path).unwrap()).unwrap();
+
+        assert_eq!(record["category"], "parse");
+        assert_eq!(record["original_path"], "./test_dead_letter/job.json");
+        assert_eq!(record["line"], 2);
+        // the offset points at the `o` of `oops`
+        assert_eq!(record["offset"], 20);
+        assert!(record["timestamp"].is_string());
+
+        // another failed job file of the same name keeps the first one
+        std::fs::write(&job_path, "{}").unwrap();
+
+        let other = write_dead_letter(&dead_letter_path, &job_path, &InvalidJob("other"))
+            .await
+            .unwrap();
+
+        assert_ne!(other, record_path);
+
+        assert!(record_path.exists() && copy_path.exists());
+
+        assert_eq!(std::fs::read_dir(&dead_letter_path).unwrap().count(), 4);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_category() {
+        assert_eq!(
+            Category::of(&ProcessorNotFound("com.unknown".into())),
+            Category::ProcessorNotFound
+        );
+
+        let error = serde_json::from_str::<Vec<u8>>("{}").unwrap_err();
+
+        assert_eq!(Category::of(&SerdeJson(error)), Category::Validation);
+    }
+}
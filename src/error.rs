@@ -1,12 +1,20 @@
 use http::header::{InvalidHeaderName, InvalidHeaderValue};
 use http::method::InvalidMethod;
+use http::status::InvalidStatusCode;
 use notify::Error as NotifyError;
 use reqwest::Error as ReqwestError;
 use serde_json::Error as SerdeJsonError;
-use std::sync::mpsc::RecvError as MpscRecvError;
+use std::{
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
 use tokio::task::JoinError as TokioJoinError;
 use url::ParseError as UrlParseError;
 
+#[cfg(feature = "wasm")]
+use crate::outcome::WasmOutput;
+use crate::outcome::{Attempt, CommandOutput};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("notify error: {0}")]
@@ -14,8 +22,10 @@ pub enum Error {
     // 多个notify error
     #[error("notify errors: {0:?}")]
     Notifies(Vec<NotifyError>),
-    #[error("mpsc recv error: {0}")]
-    MpscRecv(#[from] MpscRecvError),
+    #[error("channel closed: {0}")]
+    ChannelClosed(String),
+    #[error("the job queue is closed")]
+    JobQueueClosed,
     #[error("the specified path is a file: {0}")]
     NotDirectory(String),
     #[error("the specified path does not exist: {0}")]
@@ -34,6 +44,99 @@ pub enum Error {
     InvalidHeaderValue(#[from] InvalidHeaderValue),
     #[error("reqwest error: {0}")]
     Reqwest(#[from] ReqwestError),
+    #[error("invalid status code: {0}")]
+    InvalidStatusCode(#[from] InvalidStatusCode),
+    #[error("request failed after {} attempts: {source}", attempts.len())]
+    RequestAttempts {
+        source: ReqwestError,
+        attempts: Vec<Attempt>,
+    },
+    #[error("{0}")]
+    Config(#[from] ConfigError),
     #[error("processor not found: {0}")]
     ProcessorNotFound(String),
+    #[error("invalid job: {0}")]
+    InvalidJob(&'static str),
+    #[error("endpoint not found: {0}")]
+    EndpointNotFound(String),
+    #[error("invalid endpoints in {path:?}: {message}")]
+    InvalidEndpoints { path: PathBuf, message: String },
+    #[error("command not allowed: {0}")]
+    CommandNotAllowed(String),
+    #[error("environment variable not allowed: {0}")]
+    EnvNotAllowed(String),
+    #[error("command {0}")]
+    CommandFailed(Box<CommandOutput>),
+    #[error("invalid processor output: {0}")]
+    InvalidOutput(String),
+    #[error("invalid output of processor {program:?}: {message}")]
+    InvalidProcessorOutput { program: PathBuf, message: String },
+    #[error("host not allowed: {0}")]
+    HostNotAllowed(String),
+    #[error("response larger than {0} bytes")]
+    ResponseTooLarge(usize),
+    #[cfg(feature = "wasm")]
+    #[error("wasm error: {0:#}")]
+    Wasm(#[from] wasmtime::Error),
+    #[cfg(feature = "wasm")]
+    #[error("module {0}")]
+    WasmFailed(Box<WasmOutput>),
+    #[error("the service has no config")]
+    NoConfig,
+    #[error("the service is already running")]
+    AlreadyRunning,
+}
+
+/// Every problem found in a config file, so they can all be fixed at once
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub problems: Vec<ConfigProblem>,
+}
+
+/// A single problem found in a config file
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigProblem {
+    #[error("failed to read the config file: {0}")]
+    Read(String),
+    #[error("failed to parse the config file: {0}")]
+    Parse(String),
+    #[error("did not find {0} in config file")]
+    MissingKey(String),
+    #[error("unknown config key {0}")]
+    UnknownKey(String),
+    #[error("{key} must be {expected}, got {value}")]
+    InvalidValue {
+        key: String,
+        expected: &'static str,
+        value: String,
+    },
+    #[error("failed to parse whitelist pattern {pattern}: {message}")]
+    InvalidGlob { pattern: String, message: String },
+    #[error("watch rule {name}: {problem}")]
+    Rule {
+        name: String,
+        problem: Box<ConfigProblem>,
+    },
+    #[error("the path {0:?} is not a directory")]
+    NotDirectory(PathBuf),
+    #[error("failed to create path {path:?}: {source}")]
+    CreateDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config file {:?}:", self.path)?;
+
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+
+        Ok(())
+    }
 }
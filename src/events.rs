@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::{stream::unfold, Stream};
+use serde::Serialize;
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use tracing::warn;
+
+use crate::{error::Error, journal::JobState};
+
+/// How many events a slow subscriber may fall behind before it misses some
+const EVENT_CAPACITY: usize = 1024;
+
+/// A job which changed its state, as recorded in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JobEvent {
+    pub path: PathBuf,
+    pub state: JobState,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Receives every job event, e.g. to forward them to a message queue
+#[async_trait]
+pub trait Sink: Debug + Send + Sync + 'static {
+    async fn send(&self, event: &JobEvent) -> Result<(), Error>;
+}
+
+/// Hands the job events out to the subscribers, and counts them
+#[derive(Debug)]
+pub struct Events {
+    sender: Sender<JobEvent>,
+    queued: AtomicU64,
+    running: AtomicU64,
+    cancelled: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Self {
+            sender,
+            queued: AtomicU64::new(0),
+            running: AtomicU64::new(0),
+            cancelled: AtomicU64::new(0),
+            succeeded: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn emit(&self, path: &Path, state: JobState) {
+        self.counter(state).fetch_add(1, Ordering::Relaxed);
+
+        // nobody listening is fine
+        let _ = self.sender.send(JobEvent {
+            path: path.to_owned(),
+            state,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// How many jobs went into this state so far
+    pub fn count(&self, state: JobState) -> u64 {
+        self.counter(state).load(Ordering::Relaxed)
+    }
+
+    /// The events from now on, a subscriber which falls too far behind misses the oldest ones
+    pub fn subscribe(&self) -> impl Stream<Item = JobEvent> + Send + Unpin + 'static {
+        Box::pin(unfold(self.receiver(), |mut receiver| async move {
+            let event = next(&mut receiver).await?;
+
+            Some((event, receiver))
+        }))
+    }
+
+    pub fn receiver(&self) -> Receiver<JobEvent> {
+        self.sender.subscribe()
+    }
+
+    fn counter(&self, state: JobState) -> &AtomicU64 {
+        match state {
+            JobState::Queued => &self.queued,
+            JobState::Running => &self.running,
+            JobState::Cancelled => &self.cancelled,
+            JobState::Succeeded => &self.succeeded,
+            JobState::Failed => &self.failed,
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The next event, `None` once no more events can come
+pub async fn next(receiver: &mut Receiver<JobEvent>) -> Option<JobEvent> {
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(skipped)) => warn!("job events lagged, missed {}", skipped),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
@@ -1,14 +1,110 @@
-use globset::GlobSet;
-use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use notify_debouncer_full::{new_debouncer, DebouncedEvent, Debouncer, FileIdMap};
-use std::{path::PathBuf, sync::mpsc::Sender, time::Duration};
-use tokio::fs::create_dir_all;
-use tracing::error;
+use notify::{
+    event::{CreateKind, ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use notify_debouncer_full::{
+    new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, FileIdMap,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::Metadata,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    fs::{create_dir_all, metadata, read_dir},
+    sync::mpsc::UnboundedSender,
+    time::sleep,
+};
+use tracing::{debug, error, warn};
 
 use crate::error::Error::{self, DirDoesNotExist, NotDirectory};
 
+#[derive(Debug, Clone)]
 pub struct FileWatcher {
     path: PathBuf,
+    recursive: bool,
+    /// How deep a recursive scan goes, 1 for the watched directory itself
+    max_depth: Option<usize>,
+}
+
+/// How the changes in a watched directory are noticed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The events of the operating system, e.g. inotify
+    #[default]
+    Native,
+    /// Scan the directory at an interval, for network filesystems where events from other hosts never arrive
+    Poll,
+    /// The native events, plus a scan at an interval to catch what they missed
+    Hybrid,
+}
+
+/// Finds the job files which are new or changed since the last poll
+#[derive(Debug)]
+pub struct Poller {
+    watcher: FileWatcher,
+    seen: HashMap<PathBuf, Snapshot>,
+}
+
+/// What a poll remembers of a file to tell whether it changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    modified: SystemTime,
+    len: u64,
+    /// Tells a new file apart from the one it replaced, even with the same size and a coarse
+    /// modification time as on NFS or SMB
+    id: FileId,
+}
+
+/// The device and the inode of the file
+#[cfg(unix)]
+type FileId = (u64, u64);
+
+/// When the file was created, as there is no inode
+#[cfg(not(unix))]
+type FileId = Option<SystemTime>;
+
+/// Why the events of a watcher can no longer be trusted, so the directory has to be scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rescan {
+    /// The event queue overflowed, or the backend lost events some other way
+    Overflow,
+    /// The watched directory was removed or moved away, its watch is gone with it
+    RootRemoved,
+    /// The watcher reported errors
+    Errors,
+}
+
+/// How often the watchers lost events, a rescan is a warning sign rather than a failure
+#[derive(Debug, Default)]
+pub struct WatcherMetrics {
+    rescans: AtomicU64,
+    rewatches: AtomicU64,
+    /// How many watched directories are gone right now
+    missing_roots: AtomicU64,
+}
+
+/// Counts a watched directory as missing, until it is dropped
+#[derive(Debug)]
+pub struct MissingRoot<'a> {
+    metrics: &'a WatcherMetrics,
+}
+
+/// The kinds of file system events which turn a file into a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// A file is created in place
+    Create,
+    /// A file is renamed or moved into place, e.g. `job.json.tmp` to `job.json`
+    Rename,
 }
 
 impl FileWatcher {
@@ -30,35 +126,359 @@ impl FileWatcher {
             )));
         }
 
-        Ok(Self { path })
+        Ok(Self {
+            path,
+            recursive: false,
+            max_depth: None,
+        })
+    }
+
+    /// Watch and scan the subdirectories too
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+
+        self
+    }
+
+    /// Limit how deep a recursive scan goes, 1 for the watched directory itself
+    ///
+    /// The native watcher has no such limit, so the events have to be filtered too
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+
+        self
     }
 
     pub fn debouncer(
         &self,
-        sender: Sender<Result<Vec<DebouncedEvent>, Vec<notify::Error>>>,
+        timeout: Duration,
+        sender: UnboundedSender<DebounceEventResult>,
     ) -> Result<Debouncer<RecommendedWatcher, FileIdMap>, Error> {
-        let mut debouncer = new_debouncer(Duration::from_millis(1), None, sender)?;
+        let mut debouncer = new_debouncer(timeout, None, move |result| {
+            // the receiver is only dropped when the service stops listening
+            let _ = sender.send(result);
+        })?;
+
+        let mode = if self.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
 
-        debouncer
-            .watcher()
-            .watch(&self.path, RecursiveMode::NonRecursive)?;
+        debouncer.watcher().watch(&self.path, mode)?;
 
         Ok(debouncer)
     }
+
+    /// Wait until the watched path is a directory again, checking at the interval
+    pub async fn wait_for_root(&self, interval: Duration) {
+        while !self.path.is_dir() {
+            sleep(interval).await;
+        }
+    }
+
+    /// List the files already in the watched path which are job files, oldest first
+    pub async fn scan(&self, matches: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, Error> {
+        let files = self.list(matches).await?;
+
+        Ok(files.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// A poller which starts with nothing seen, so its first poll returns every job file
+    pub fn poller(&self) -> Poller {
+        Poller {
+            watcher: self.clone(),
+            seen: HashMap::new(),
+        }
+    }
+
+    async fn list(
+        &self,
+        matches: impl Fn(&Path) -> bool,
+    ) -> Result<Vec<(PathBuf, Snapshot)>, Error> {
+        let mut dirs = vec![(self.path.clone(), 1)];
+
+        let mut files = Vec::new();
+
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = match read_dir(&dir).await {
+                Ok(entries) => entries,
+                // a subdirectory removed since it was listed
+                Err(error) if error.kind() == ErrorKind::NotFound && dir != self.path => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+
+                let metadata = match entry.metadata().await {
+                    Ok(metadata) => metadata,
+                    // gone between listing the directory and looking at the file
+                    Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                    Err(error) => return Err(error.into()),
+                };
+
+                if metadata.is_dir() {
+                    if self.recursive && self.max_depth.is_none_or(|max_depth| depth < max_depth) {
+                        dirs.push((path, depth + 1));
+                    }
+
+                    continue;
+                }
+
+                if !metadata.is_file() || !matches(&path) {
+                    continue;
+                }
+
+                let snapshot = Snapshot {
+                    modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    len: metadata.len(),
+                    id: file_id(&metadata),
+                };
+
+                files.push((path, snapshot));
+            }
+        }
+
+        files.sort_by(|(a, a_snapshot), (b, b_snapshot)| {
+            (a_snapshot.modified, a).cmp(&(b_snapshot.modified, b))
+        });
+
+        Ok(files)
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(metadata: &Metadata) -> FileId {
+    metadata.created().ok()
+}
+
+impl Poller {
+    /// Return the job files which are new or changed since the last poll, oldest first
+    ///
+    /// A file which is not matched yet, e.g. still waiting for its ready marker, is checked again
+    /// on the next poll. A file which disappears is forgotten, so it is new again if it comes back
+    pub async fn poll(&mut self, matches: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, Error> {
+        let files = self.watcher.list(matches).await?;
+
+        let mut seen = HashMap::with_capacity(files.len());
+
+        let mut changed = Vec::new();
+
+        for (path, snapshot) in files {
+            if self.seen.get(&path) != Some(&snapshot) {
+                changed.push(path.clone());
+            }
+
+            seen.insert(path, snapshot);
+        }
+
+        self.seen = seen;
+
+        Ok(changed)
+    }
 }
 
+impl Trigger {
+    fn matches(&self, kind: &EventKind) -> bool {
+        match self {
+            // * CreateKind::Any for windows
+            Trigger::Create => matches!(
+                kind,
+                EventKind::Create(CreateKind::File) | EventKind::Create(CreateKind::Any)
+            ),
+            // * RenameMode::Any for mac os, which reports both sides of a rename the same way
+            Trigger::Rename => matches!(
+                kind,
+                EventKind::Modify(ModifyKind::Name(
+                    RenameMode::To | RenameMode::Both | RenameMode::Any
+                ))
+            ),
+        }
+    }
+}
+
+impl Rescan {
+    /// Whether the watch itself has to be set up again, not just the directory scanned
+    pub fn rewatch(&self) -> bool {
+        matches!(self, Rescan::RootRemoved | Rescan::Errors)
+    }
+
+    /// Find out from the events whether any were lost
+    pub fn of(events: &[DebouncedEvent], root: &Path) -> Option<Self> {
+        let root_removed = events.iter().any(|event| {
+            matches!(
+                event.kind,
+                EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+            ) && event.paths.iter().any(|path| path == root)
+        });
+
+        if root_removed {
+            Some(Rescan::RootRemoved)
+        } else if events.iter().any(|event| event.need_rescan()) {
+            Some(Rescan::Overflow)
+        } else {
+            None
+        }
+    }
+}
+
+impl WatcherMetrics {
+    /// Count a rescan of the rule and warn about it
+    pub fn record(&self, rule: &str, rescan: Rescan) {
+        let rescans = self.rescans.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if rescan.rewatch() {
+            self.rewatches.fetch_add(1, Ordering::Relaxed);
+        }
+
+        warn!(
+            "watch rule {} lost events ({:?}), rescanning, {} rescans so far",
+            rule, rescan, rescans
+        );
+    }
+
+    /// How many times a watcher lost events
+    pub fn rescans(&self) -> u64 {
+        self.rescans.load(Ordering::Relaxed)
+    }
+
+    /// How many times a watch had to be set up again
+    pub fn rewatches(&self) -> u64 {
+        self.rewatches.load(Ordering::Relaxed)
+    }
+
+    /// Count the watched directory of the rule as missing and warn about it
+    pub fn missing_root(&self, rule: &str, path: &Path) -> MissingRoot<'_> {
+        self.missing_roots.fetch_add(1, Ordering::Relaxed);
+
+        warn!(
+            "watched directory {:?} of rule {} is gone, waiting for it to come back",
+            path, rule
+        );
+
+        MissingRoot { metrics: self }
+    }
+
+    /// How many watched directories are gone right now
+    pub fn missing_roots(&self) -> u64 {
+        self.missing_roots.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MissingRoot<'_> {
+    fn drop(&mut self) {
+        self.metrics.missing_roots.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "native" => Ok(Backend::Native),
+            "poll" => Ok(Backend::Poll),
+            "hybrid" => Ok(Backend::Hybrid),
+            other => Err(format!("unknown watcher backend: {}", other)),
+        }
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "create" => Ok(Trigger::Create),
+            "rename" => Ok(Trigger::Rename),
+            other => Err(format!("unknown trigger event: {}", other)),
+        }
+    }
+}
+
+/// Keep the events matching the triggers whose paths are job files, return the paths
+///
+/// With `ready_marker`, a job file is only returned once its `.ready` marker shows up
 pub fn filter_events(
     events: Vec<DebouncedEvent>,
-    kinds: Vec<EventKind>,
-    globset: GlobSet,
-) -> Vec<DebouncedEvent> {
-    let mut events = events;
+    triggers: &[Trigger],
+    matches: impl Fn(&Path) -> bool,
+    ready_marker: bool,
+) -> Vec<PathBuf> {
+    events
+        .into_iter()
+        .filter(|event| triggers.iter().any(|trigger| trigger.matches(&event.kind)))
+        .filter_map(|event| {
+            let is_rename = matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
 
-    events.retain(|event| {
-        kinds.contains(&event.kind) && event.paths.iter().any(|path| globset.is_match(path))
-    });
+            // the destination of a rename is the last path
+            let path = event.event.paths.into_iter().last()?;
 
-    events
+            // the source side of an ambiguous rename no longer exists
+            if is_rename && !path.is_file() {
+                return None;
+            }
+
+            #[cfg(target_os = "windows")]
+            if path.is_dir() {
+                return None;
+            }
+
+            let path = if ready_marker {
+                job_path_from_ready_marker(&path)?
+            } else {
+                path
+            };
+
+            matches(&path).then_some(path)
+        })
+        .collect()
+}
+
+/// The marker which tells that a job file is completely written, e.g. `job.json.ready`
+pub fn ready_marker_path(path: &Path) -> PathBuf {
+    let mut marker = OsString::from(path.as_os_str());
+
+    marker.push(READY_MARKER_EXTENSION);
+
+    PathBuf::from(marker)
+}
+
+fn job_path_from_ready_marker(marker: &Path) -> Option<PathBuf> {
+    let marker = marker.to_str()?;
+
+    marker
+        .strip_suffix(READY_MARKER_EXTENSION)
+        .map(PathBuf::from)
+}
+
+const READY_MARKER_EXTENSION: &str = ".ready";
+
+/// Wait until the size and the modification time of the file stop changing for the quiet period
+pub async fn wait_until_stable(path: &Path, quiet_period: Duration) -> Result<(), Error> {
+    let mut last = metadata(path).await?;
+
+    loop {
+        sleep(quiet_period).await;
+
+        let current = metadata(path).await?;
+
+        if current.len() == last.len() && current.modified().ok() == last.modified().ok() {
+            return Ok(());
+        }
+
+        debug!("{:?} is still being written", path);
+
+        last = current;
+    }
 }
 
 #[cfg(test)]
@@ -68,9 +488,14 @@ mod tests {
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     use globset::{Glob, GlobSetBuilder};
     #[cfg(any(target_os = "linux", target_os = "macos"))]
-    use notify::{event::CreateKind, Event};
+    use notify::{
+        event::{CreateKind, Flag, RemoveKind},
+        Event,
+    };
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    use std::time::Instant;
     #[cfg(any(target_os = "linux", target_os = "macos"))]
-    use std::{sync::mpsc::channel, time::Instant};
+    use tokio::sync::mpsc::unbounded_channel;
 
     #[test]
     #[cfg(any(target_os = "linux", target_os = "macos"))]
@@ -89,7 +514,12 @@ mod tests {
         builder.add(Glob::new("*.txt").unwrap());
         let globset = builder.build().unwrap();
 
-        let events = filter_events(events, vec![EventKind::Create(CreateKind::File)], globset);
+        let events = filter_events(
+            events,
+            &[Trigger::Create],
+            |path| globset.is_match(path),
+            false,
+        );
 
         assert_eq!(events.len(), 1);
     }
@@ -111,11 +541,300 @@ mod tests {
         builder.add(Glob::new("*.json").unwrap());
         let globset = builder.build().unwrap();
 
-        let events = filter_events(events, vec![EventKind::Create(CreateKind::File)], globset);
+        let events = filter_events(
+            events,
+            &[Trigger::Create],
+            |path| globset.is_match(path),
+            false,
+        );
 
         assert_eq!(events.len(), 0);
     }
 
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn test_filter_rename_events() {
+        let path = PathBuf::from("./test_filter_rename_events");
+
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("job.txt"), "job").unwrap();
+
+        let rename = |mode: RenameMode, paths: &[&str]| {
+            let event = paths.iter().fold(
+                Event::new(EventKind::Modify(ModifyKind::Name(mode))),
+                |event, name| event.add_path(path.join(name)),
+            );
+
+            DebouncedEvent::new(event, Instant::now())
+        };
+
+        let events = vec![
+            rename(RenameMode::Both, &["job.txt.tmp", "job.txt"]),
+            rename(RenameMode::To, &["job.txt"]),
+            rename(RenameMode::From, &["job.txt"]),
+            // the file was renamed away
+            rename(RenameMode::Both, &["gone.txt", "gone.txt.bak"]),
+        ];
+
+        let globset = GlobSetBuilder::new()
+            .add(Glob::new("*.txt").unwrap())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            filter_events(
+                events.clone(),
+                &[Trigger::Create],
+                |path| globset.is_match(path),
+                false
+            )
+            .len(),
+            0
+        );
+
+        assert_eq!(
+            filter_events(
+                events,
+                &[Trigger::Create, Trigger::Rename],
+                |path| globset.is_match(path),
+                false
+            ),
+            vec![path.join("job.txt"), path.join("job.txt")]
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn test_filter_ready_marker_events() {
+        let create = |path: &str| {
+            DebouncedEvent::new(
+                Event::new(EventKind::Create(CreateKind::File)).add_path(path.into()),
+                Instant::now(),
+            )
+        };
+
+        let events = vec![
+            create("job.txt"),
+            create("job.txt.ready"),
+            create("job.json.ready"),
+        ];
+
+        let globset = GlobSetBuilder::new()
+            .add(Glob::new("*.txt").unwrap())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            filter_events(
+                events,
+                &[Trigger::Create],
+                |path| globset.is_match(path),
+                true
+            ),
+            vec![PathBuf::from("job.txt")]
+        );
+
+        assert_eq!(
+            ready_marker_path(Path::new("listen/job.txt")),
+            PathBuf::from("listen/job.txt.ready")
+        );
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn test_rescan() {
+        let event = |kind: EventKind, path: &str| {
+            DebouncedEvent::new(Event::new(kind).add_path(path.into()), Instant::now())
+        };
+
+        let root = Path::new("/listen");
+
+        let created = event(EventKind::Create(CreateKind::File), "/listen/job.json");
+
+        assert_eq!(Rescan::of(std::slice::from_ref(&created), root), None);
+
+        let overflow = DebouncedEvent::new(
+            Event::new(EventKind::Other).set_flag(Flag::Rescan),
+            Instant::now(),
+        );
+
+        assert_eq!(
+            Rescan::of(&[created.clone(), overflow], root),
+            Some(Rescan::Overflow)
+        );
+
+        let removed = event(EventKind::Remove(RemoveKind::Folder), "/listen");
+
+        assert_eq!(
+            Rescan::of(&[created, removed], root),
+            Some(Rescan::RootRemoved)
+        );
+
+        // only the root itself matters
+        let removed = event(EventKind::Remove(RemoveKind::Folder), "/listen/2024");
+
+        assert_eq!(Rescan::of(&[removed], root), None);
+
+        let metrics = WatcherMetrics::default();
+
+        metrics.record("default", Rescan::Overflow);
+        metrics.record("default", Rescan::RootRemoved);
+
+        assert_eq!(metrics.rescans(), 2);
+
+        assert_eq!(metrics.rewatches(), 1);
+
+        let missing = metrics.missing_root("default", root);
+
+        assert_eq!(metrics.missing_roots(), 1);
+
+        drop(missing);
+
+        assert_eq!(metrics.missing_roots(), 0);
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_wait_until_stable() {
+        let path = PathBuf::from("./test_wait_until_stable.txt");
+
+        std::fs::write(&path, "job").unwrap();
+
+        let writer = {
+            let path = path.clone();
+
+            tokio::spawn(async move {
+                for _ in 0..3 {
+                    sleep(Duration::from_millis(20)).await;
+
+                    let mut content = std::fs::read(&path).unwrap();
+                    content.extend_from_slice(b" more");
+                    std::fs::write(&path, content).unwrap();
+                }
+            })
+        };
+
+        wait_until_stable(&path, Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        writer.await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"job more more more");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_scan() {
+        let path = PathBuf::from("./test_scan");
+
+        std::fs::create_dir_all(path.join("folder.txt")).unwrap();
+
+        for name in ["b.txt", "a.txt", "c.json"] {
+            std::fs::write(path.join(name), name).unwrap();
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let file_watcher = FileWatcher::new(path.clone()).await.unwrap();
+
+        let globset = GlobSetBuilder::new()
+            .add(Glob::new("*.txt").unwrap())
+            .build()
+            .unwrap();
+
+        let files = file_watcher
+            .scan(|path| globset.is_match(path))
+            .await
+            .unwrap();
+
+        assert_eq!(files, vec![path.join("b.txt"), path.join("a.txt")]);
+
+        std::fs::create_dir_all(path.join("folder.txt/deeper")).unwrap();
+        std::fs::write(path.join("folder.txt/d.txt"), "d").unwrap();
+        std::fs::write(path.join("folder.txt/deeper/e.txt"), "e").unwrap();
+
+        let files = FileWatcher::new(path.clone())
+            .await
+            .unwrap()
+            .recursive(true)
+            .max_depth(Some(2))
+            .scan(|path| globset.is_match(path))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            files,
+            vec![
+                path.join("b.txt"),
+                path.join("a.txt"),
+                path.join("folder.txt/d.txt")
+            ]
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_poller() {
+        let path = PathBuf::from("./test_poller");
+
+        std::fs::create_dir_all(&path).unwrap();
+
+        let job = path.join("job.txt");
+
+        let mut poller = FileWatcher::new(path.clone()).await.unwrap().poller();
+
+        let matches = |path: &Path| path.extension().is_some_and(|extension| extension == "txt");
+
+        assert!(poller.poll(matches).await.unwrap().is_empty());
+
+        std::fs::write(&job, "job").unwrap();
+        std::fs::write(path.join("job.json"), "job").unwrap();
+
+        assert_eq!(poller.poll(matches).await.unwrap(), vec![job.clone()]);
+
+        // nothing changed since
+        assert!(poller.poll(matches).await.unwrap().is_empty());
+
+        // replaced between two polls
+        std::fs::write(&job, "another job").unwrap();
+
+        assert_eq!(poller.poll(matches).await.unwrap(), vec![job.clone()]);
+
+        // gone and back between two polls, with the same content
+        std::fs::remove_file(&job).unwrap();
+
+        assert!(poller.poll(matches).await.unwrap().is_empty());
+
+        std::fs::write(&job, "another job").unwrap();
+
+        assert_eq!(poller.poll(matches).await.unwrap(), vec![job.clone()]);
+
+        // archived and replaced between two polls, with the same size and modification time
+        let modified = std::fs::metadata(&job).unwrap().modified().unwrap();
+
+        std::fs::rename(&job, path.join("archived.json")).unwrap();
+
+        std::fs::write(&job, "other job!!").unwrap();
+
+        std::fs::File::options()
+            .write(true)
+            .open(&job)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert_eq!(poller.poll(matches).await.unwrap(), vec![job]);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
     #[tokio::test]
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     async fn test_file_watcher() {
@@ -125,32 +844,37 @@ mod tests {
 
         let file_watcher = FileWatcher::new(path.clone()).await.unwrap();
 
-        let (tx, rx) = channel();
+        let (tx, mut rx) = unbounded_channel();
 
-        let _debouncer = file_watcher.debouncer(tx).unwrap();
+        let _debouncer = file_watcher
+            .debouncer(Duration::from_millis(1), tx)
+            .unwrap();
 
         std::fs::write(path.join("foo.txt"), "foo").unwrap();
 
-        match rx.recv() {
-            Ok(Ok(debounced_events)) => {
+        match rx.recv().await {
+            Some(Ok(debounced_events)) => {
                 println!("events: {:?}", debounced_events);
 
+                let globset = GlobSetBuilder::new()
+                    .add(Glob::new("*.txt").unwrap())
+                    .build()
+                    .unwrap();
+
                 let events = filter_events(
                     debounced_events,
-                    vec![EventKind::Create(CreateKind::File)],
-                    GlobSetBuilder::new()
-                        .add(Glob::new("*.txt").unwrap())
-                        .build()
-                        .unwrap(),
+                    &[Trigger::Create],
+                    |path| globset.is_match(path),
+                    false,
                 );
 
                 assert_eq!(events.len(), 1);
             }
-            Ok(Err(errors)) => {
+            Some(Err(errors)) => {
                 error!("notify error: {:?}", errors);
             }
-            Err(error) => {
-                error!("mpsc recv error: {:?}", error);
+            None => {
+                error!("event channel closed");
             }
         }
     }
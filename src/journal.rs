@@ -0,0 +1,356 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{create_dir_all, read_to_string, rename, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+use crate::error::Error;
+
+/// A durable, append-only log of the state of every job file
+///
+/// A job is identified by its path plus the hash of its content, so a job file is never
+/// processed successfully twice, and jobs which were queued or running when the service stopped are resumed
+///
+/// Only the most recently finished jobs are remembered, at least `retention` and at most twice as
+/// many, an identical copy of an older one is processed again
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    retention: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    /// The latest entry of jobs which are not finished yet, by path
+    pending: HashMap<PathBuf, Entry>,
+    /// The finished jobs, by path and content hash
+    completed: HashMap<(PathBuf, String), Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    state: JobState,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Stopped by a shutdown or left alone since its watch rule was removed, resumed on the next
+    /// start
+    Cancelled,
+    Succeeded,
+    Failed,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed)
+    }
+}
+
+impl Journal {
+    /// Open the journal in the state directory, replaying and compacting the existing log
+    pub async fn open(state_path: &Path, retention: usize) -> Result<Self, Error> {
+        if !state_path.exists() {
+            create_dir_all(state_path).await?;
+        }
+
+        let path = state_path.join("journal.log");
+
+        let mut pending = HashMap::new();
+        let mut completed = HashMap::new();
+
+        if path.exists() {
+            for (number, line) in read_to_string(&path).await?.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                // a crash can leave the last line half written
+                let entry = match from_str::<Entry>(line) {
+                    Ok(entry) => entry,
+                    Err(error) => {
+                        warn!("skip broken journal line {}: {}", number + 1, error);
+
+                        continue;
+                    }
+                };
+
+                apply(&mut pending, &mut completed, entry);
+            }
+        }
+
+        prune(&mut completed, retention);
+
+        let file = compact(&path, completed.values().chain(pending.values())).await?;
+
+        info!(
+            "journal opened: {} finished, {} pending",
+            completed.len(),
+            pending.len()
+        );
+
+        Ok(Self {
+            path,
+            retention,
+            inner: Mutex::new(Inner {
+                file,
+                pending,
+                completed,
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append the new state of the job to the log
+    pub async fn record(
+        &self,
+        path: &Path,
+        hash: Option<&str>,
+        state: JobState,
+    ) -> Result<(), Error> {
+        let entry = Entry {
+            path: path.to_owned(),
+            hash: hash.map(str::to_owned),
+            state,
+            timestamp: Utc::now(),
+        };
+
+        let line = format!("{}\n", to_string(&entry)?);
+
+        let mut inner = self.inner.lock().await;
+
+        inner.file.write_all(line.as_bytes()).await?;
+
+        inner.file.sync_data().await?;
+
+        let Inner {
+            file,
+            pending,
+            completed,
+        } = &mut *inner;
+
+        apply(pending, completed, entry);
+
+        // pruned in batches, so the log is not rewritten for every finished job
+        if completed.len() > self.retention * 2 {
+            prune(completed, self.retention);
+
+            *file = compact(&self.path, completed.values().chain(pending.values())).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the job with this content was already processed successfully
+    ///
+    /// Failed jobs are not skipped, so a dead job can be replayed by submitting it again
+    pub async fn has_succeeded(&self, path: &Path, hash: &str) -> bool {
+        self.inner
+            .lock()
+            .await
+            .completed
+            .get(&(path.to_owned(), hash.to_owned()))
+            .is_some_and(|entry| entry.state == JobState::Succeeded)
+    }
+
+    /// The jobs which were queued, running or cancelled, oldest first
+    pub async fn pending(&self) -> Vec<PathBuf> {
+        let inner = self.inner.lock().await;
+
+        let mut entries = inner.pending.values().collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| (a.timestamp, &a.path).cmp(&(b.timestamp, &b.path)));
+
+        entries
+            .into_iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    /// Make sure everything is on disk
+    pub async fn flush(&self) -> Result<(), Error> {
+        let mut inner = self.inner.lock().await;
+
+        inner.file.flush().await?;
+
+        inner.file.sync_all().await?;
+
+        Ok(())
+    }
+}
+
+fn apply(
+    pending: &mut HashMap<PathBuf, Entry>,
+    completed: &mut HashMap<(PathBuf, String), Entry>,
+    entry: Entry,
+) {
+    if !entry.state.is_finished() {
+        pending.insert(entry.path.clone(), entry);
+
+        return;
+    }
+
+    pending.remove(&entry.path);
+
+    if let Some(hash) = &entry.hash {
+        completed.insert((entry.path.clone(), hash.clone()), entry);
+    }
+}
+
+/// Forget all but the `retention` most recently finished jobs
+fn prune(completed: &mut HashMap<(PathBuf, String), Entry>, retention: usize) {
+    if completed.len() <= retention {
+        return;
+    }
+
+    let mut timestamps = completed
+        .values()
+        .map(|entry| entry.timestamp)
+        .collect::<Vec<_>>();
+
+    timestamps.sort_unstable();
+
+    let oldest = timestamps[timestamps.len() - retention.max(1)];
+
+    completed.retain(|_, entry| entry.timestamp >= oldest);
+}
+
+/// Rewrite the log with only the given entries, so it does not grow with every state change,
+/// and open it for appending
+async fn compact(path: &Path, entries: impl Iterator<Item = &Entry>) -> Result<File, Error> {
+    let compacted = path.with_extension("log.tmp");
+
+    let mut file = File::create(&compacted).await?;
+
+    for entry in entries {
+        file.write_all(format!("{}\n", to_string(entry)?).as_bytes())
+            .await?;
+    }
+
+    file.sync_all().await?;
+
+    rename(&compacted, path).await?;
+
+    Ok(OpenOptions::new().append(true).open(path).await?)
+}
+
+/// The hash identifying the content of a job file
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_journal() {
+        let state_path = PathBuf::from("./test_journal");
+
+        let _ = std::fs::remove_dir_all(&state_path);
+
+        let finished = Path::new("listen/finished.json");
+        let running = Path::new("listen/running.json");
+        let queued = Path::new("listen/queued.json");
+
+        {
+            let journal = Journal::open(&state_path, 10).await.unwrap();
+
+            journal
+                .record(finished, None, JobState::Queued)
+                .await
+                .unwrap();
+            journal
+                .record(running, None, JobState::Queued)
+                .await
+                .unwrap();
+            journal
+                .record(queued, None, JobState::Queued)
+                .await
+                .unwrap();
+
+            journal
+                .record(finished, Some("a"), JobState::Running)
+                .await
+                .unwrap();
+            journal
+                .record(finished, Some("a"), JobState::Succeeded)
+                .await
+                .unwrap();
+            journal
+                .record(running, Some("b"), JobState::Running)
+                .await
+                .unwrap();
+        }
+
+        // reopen as if the service crashed
+        let journal = Journal::open(&state_path, 10).await.unwrap();
+
+        assert!(journal.has_succeeded(finished, "a").await);
+        assert!(!journal.has_succeeded(finished, "changed").await);
+        assert!(!journal.has_succeeded(running, "b").await);
+
+        assert_eq!(
+            journal.pending().await,
+            vec![queued.to_owned(), running.to_owned()]
+        );
+
+        let lines = std::fs::read_to_string(journal.path())
+            .unwrap()
+            .lines()
+            .count();
+
+        assert_eq!(lines, 3);
+
+        drop(journal);
+
+        // only the most recently finished jobs are kept
+        let journal = Journal::open(&state_path, 2).await.unwrap();
+
+        for hash in ["b", "c", "d", "e"] {
+            journal
+                .record(finished, Some(hash), JobState::Succeeded)
+                .await
+                .unwrap();
+        }
+
+        // pruned once there were more than twice as many
+        for hash in ["a", "b", "c"] {
+            assert!(!journal.has_succeeded(finished, hash).await);
+        }
+
+        for hash in ["d", "e"] {
+            assert!(journal.has_succeeded(finished, hash).await);
+        }
+
+        let lines = std::fs::read_to_string(journal.path())
+            .unwrap()
+            .lines()
+            .count();
+
+        assert_eq!(lines, 4);
+
+        std::fs::remove_dir_all(state_path).unwrap();
+    }
+}
@@ -1,4 +1,11 @@
+pub mod archive;
 pub mod config;
+pub mod dead_letter;
 pub mod error;
+pub mod events;
 pub mod file_watcher;
+pub mod journal;
+pub mod outcome;
 pub mod processor;
+pub mod service;
+pub mod worker;
@@ -1,125 +1,150 @@
-use globset::GlobSet;
-use notify::{event::CreateKind, EventKind};
-use std::{
-    collections::HashMap,
-    path::PathBuf,
-    sync::{mpsc::channel, Arc},
-};
-use tokio::{fs::File, io::AsyncReadExt};
-use tracing::{error, info};
+use clap::Parser;
+use std::{future::pending, path::PathBuf, process::ExitCode};
+use tokio::{select, signal};
+use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, prelude::*, registry, EnvFilter};
 
-use fbr_service::{
-    config::Config,
-    error::Error::{self, MpscRecv, Notifies},
-    file_watcher::{filter_events, FileWatcher},
-    processor::{IOBuilder, NetworkIOProcessor, Process, Processors},
-};
+use fbr_service::{config::Config, error::Error, service::Service};
+
+#[derive(Debug, Parser)]
+#[command(author, version)]
+struct Args {
+    /// Set a custom config file, TOML for a `.toml` file and dotenv otherwise
+    #[arg(short, long, value_name = "FILE")]
+    config: PathBuf,
+    /// Override a single config key, e.g. `--set max_concurrent_jobs=8`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> Result<ExitCode, Error> {
     registry()
         .with(EnvFilter::try_from_default_env().map_or("info".into(), |env| env))
         .with(fmt::layer())
         .init();
 
-    let config = Config::instance().await;
-    let listen_path = config.listen_path().to_owned();
-    let globset = config.globset().to_owned();
+    let args = Args::parse();
+
+    let config = match Config::load_with_overrides(args.config, args.overrides).await {
+        Ok(config) => config,
+        Err(error) => {
+            error!("{}", error);
+
+            return Ok(ExitCode::FAILURE);
+        }
+    };
 
-    let network_io_processor = NetworkIOProcessor::default();
+    let service = Service::builder().config(config).build().await?;
 
-    let mut map: HashMap<_, Box<dyn Process>> = HashMap::new();
+    tokio::spawn(handle_signals(service.clone()));
 
-    map.insert("com.proxy.network.io", Box::new(network_io_processor));
+    if service.run().await? {
+        info!("shut down gracefully");
 
-    let processors = Arc::new(Processors::new(map));
+        Ok(ExitCode::SUCCESS)
+    } else {
+        warn!("shut down with cancelled jobs, they will be resumed on the next start");
 
-    listen(listen_path, globset, Arc::clone(&processors)).await?;
+        Ok(ExitCode::FAILURE)
+    }
+}
 
-    Ok(())
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_owned(), value.to_owned())),
+        None => Err(format!("expected KEY=VALUE, got {}", value)),
+    }
 }
 
-async fn listen(
-    listen_path: PathBuf,
-    globset: GlobSet,
-    processors: Arc<Processors>,
-) -> Result<(), Error> {
-    let (tx, rx) = channel();
-
-    // `let _debouncer`, avoid dropping the debouncer immediately, which will cause dropping the tx, and then the rx will be closed.
-    let _debouncer =
-        tokio::spawn(async move { FileWatcher::new(listen_path).await?.debouncer(tx) }).await??;
-
-    tokio::spawn(async move {
-        loop {
-            let res = match rx.recv() {
-                Ok(res) => res,
-                Err(e) => {
-                    error!("mpsc recv error: {}", e);
-
-                    break Err::<(), Error>(MpscRecv(e));
-                }
-            };
+/// Shut the service down on Ctrl-C or SIGTERM, reload its config on SIGHUP
+async fn handle_signals(service: Service) {
+    let shutdown = shutdown_signal();
 
-            let debounced_events = match res {
-                Ok(debounced_events) => debounced_events,
-                Err(errors) => {
-                    error!("notify errors: {:?}", errors);
+    tokio::pin!(shutdown);
 
-                    break Err(Notifies(errors));
+    let mut hangup_signal = hangup_signal();
+
+    loop {
+        select! {
+            _ = &mut shutdown => break,
+            _ = hangup(&mut hangup_signal) => {
+                info!("received SIGHUP, reloading config");
+
+                if let Err(error) = service.reload().await {
+                    error!("failed to reload, keeping the old config or endpoints: {}", error);
                 }
-            };
+            }
+        }
+    }
 
-            #[cfg(target_os = "windows")]
-            let events = filter_events(
-                debounced_events,
-                vec![
-                    // * CreateKind::Any for windows
-                    EventKind::Create(CreateKind::Any),
-                ],
-                globset.clone(),
-            );
+    service.shutdown();
+}
 
-            #[cfg(any(target_os = "linux", target_os = "macos"))]
-            let events = filter_events(
-                debounced_events,
-                vec![EventKind::Create(CreateKind::File)],
-                globset.clone(),
-            );
+/// Resolve on Ctrl-C, or on SIGTERM for unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(error) = signal::ctrl_c().await {
+            error!("failed to listen for Ctrl-C: {}", error);
 
-            let paths = events
-                .into_iter()
-                .flat_map(|event| event.event.paths)
-                .collect::<Vec<_>>();
+            pending::<()>().await;
+        }
+    };
 
-            if !paths.is_empty() {
-                for path in paths {
-                    info!("path: {:?}", path);
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(error) => {
+                error!("failed to listen for SIGTERM: {}", error);
 
-                    let mut file = File::open(path).await?;
+                pending::<()>().await;
+            }
+        }
+    };
 
-                    let mut buffer = Vec::new();
+    #[cfg(not(unix))]
+    let terminate = pending::<()>();
 
-                    file.read_to_end(&mut buffer).await?;
+    select! {
+        _ = ctrl_c => info!("received Ctrl-C"),
+        _ = terminate => info!("received SIGTERM"),
+    }
+}
 
-                    let io_builder = match IOBuilder::new(&buffer) {
-                        Ok(io_builder) => io_builder,
-                        Err(e) => {
-                            error!("parse json error: {}", e);
+#[cfg(unix)]
+type Hangup = Option<signal::unix::Signal>;
 
-                            continue;
-                        }
-                    };
+#[cfg(not(unix))]
+type Hangup = ();
 
-                    let io = io_builder.build()?;
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(error) => {
+            error!("failed to listen for SIGHUP: {}", error);
 
-                    processors.process(io).await?;
-                }
-            }
+            None
         }
-    })
-    .await??;
+    }
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {}
+
+/// Resolve on SIGHUP for unix, never otherwise
+async fn hangup(signal: &mut Hangup) {
+    #[cfg(unix)]
+    if let Some(signal) = signal {
+        signal.recv().await;
+
+        return;
+    }
+
+    let _ = signal;
 
-    Ok(())
+    pending::<()>().await;
 }
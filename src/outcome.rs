@@ -0,0 +1,68 @@
+//! What a failed job leaves behind for its result document, carried there by the errors
+
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+/// A single try of a network request, recorded in the result document
+#[derive(Debug, Serialize)]
+pub struct Attempt {
+    pub(crate) attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    pub(crate) elapsed_ms: u128,
+    /// How long we waited before the next attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) delay_ms: Option<u128>,
+}
+
+/// What a program printed, and how it ended
+#[derive(Debug, Serialize)]
+pub struct CommandOutput {
+    /// `None` if the program was killed, e.g. by the timeout
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) timed_out: bool,
+    pub(crate) stdout: String,
+    pub(crate) stdout_truncated: bool,
+    pub(crate) stderr: String,
+    pub(crate) stderr_truncated: bool,
+}
+
+/// What a WebAssembly module printed, and why it stopped
+#[cfg(feature = "wasm")]
+#[derive(Debug, Serialize)]
+pub struct WasmOutput {
+    #[serde(flatten)]
+    pub(crate) output: CommandOutput,
+    /// Why the module was stopped, if it was not by the timeout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) trap: Option<String>,
+}
+
+impl CommandOutput {
+    /// Whether the program exited with code 0
+    pub(crate) fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+impl Display for CommandOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.exit_code {
+            Some(code) => write!(f, "exited with code {}", code),
+            None if self.timed_out => write!(f, "timed out"),
+            None => write!(f, "was killed by a signal"),
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl Display for WasmOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.trap {
+            Some(_) => write!(f, "trapped"),
+            None => write!(f, "{}", self.output),
+        }
+    }
+}
@@ -1,75 +1,237 @@
 use async_trait::async_trait;
+use rand::{thread_rng, Rng};
+use reqwest::header::RETRY_AFTER;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::{Body, Client, Method, Url};
-use serde::{Deserialize, Serialize};
-use serde_json::{from_slice, to_vec};
-use std::collections::HashMap;
+use reqwest::{Body, Client, Method, Request, Response, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{from_slice, to_value, to_vec, Map, Value};
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs::{create_dir_all, File};
 use tokio::io::AsyncWriteExt;
-use tracing::{error, info};
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
 
-use crate::error::Error::{self, ProcessorNotFound};
+use crate::{
+    error::Error::{
+        self, CommandFailed, InvalidJob, InvalidOutput, ProcessorNotFound, RequestAttempts,
+    },
+    outcome::Attempt,
+};
 
-#[derive(Debug)]
+pub mod command;
+pub mod endpoint;
+pub mod external;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use endpoint::Endpoints;
+
+/// The registry of processors, by the `processor_id` of their job files
+#[derive(Debug, Default)]
 pub struct Processors {
-    inner: HashMap<&'static str, Box<dyn Process>>,
+    inner: HashMap<String, Box<dyn ErasedProcess>>,
 }
 
+/// A processor of a single kind of job
 #[async_trait]
-pub trait Process: Debug + Send + Sync {
-    async fn process(&self, io: IO) -> Result<(), Error>;
+pub trait Process: Debug + Send + Sync + 'static {
+    /// The processor specific fields of the job file, besides `processor_id` and `result_path`
+    type Job: Job;
+    /// Written to `result_path` on success, it has to serialize to a JSON object without the keys
+    /// `success`, `elapsed_ms` and `archived_path` of the result document
+    type Output: Serialize + Send;
+
+    async fn process(&self, job: Self::Job) -> Result<Self::Output, Error>;
+}
+
+/// The processor specific part of a job file
+pub trait Job: DeserializeOwned + Send + 'static {
+    /// Resolve the relative paths of the job against the given directory
+    fn relative_to(&mut self, _dir: &Path) {}
+}
+
+/// A processor with its job type erased, so processors of every kind fit into the registry
+#[async_trait]
+trait ErasedProcess: Debug + Send + Sync {
+    fn parse(&self, bytes: &[u8], dir: Option<&Path>) -> Result<Box<dyn Any + Send>, Error>;
+
+    async fn process(&self, job: Box<dyn Any + Send>) -> Result<Map<String, Value>, Error>;
+}
+
+/// The keys of the result document the output of a processor may not set
+const RESERVED_KEYS: &[&str] = &["success", "elapsed_ms", "archived_path"];
+
+/// The fields every job file has, whatever its processor
+#[derive(Debug, Deserialize)]
+struct Envelope<Id = String> {
+    processor_id: Id,
+    result_path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NetworkIOOutput {
+    status: u16,
+    headers: Vec<Header>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body_file: Option<BodyFile>,
+    attempts: Vec<Attempt>,
+}
+
+/// The response body streamed to disk
+#[derive(Debug, Serialize)]
+struct BodyFile {
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+/// The outcome of a job, along with where to write it
+#[derive(Debug)]
+pub struct Report {
+    result_path: PathBuf,
+    document: ResultDocument,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum ResultDocument {
+    Success(ProcessorSuccess),
+    Error(ProcessorError),
+    Cancelled(ProcessorCancelled),
+}
+
+#[derive(Debug, Serialize)]
+struct ProcessorSuccess {
+    success: bool,
+    #[serde(flatten)]
+    output: Map<String, Value>,
+    elapsed_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archived_path: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize)]
 struct ProcessorError {
+    success: bool,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<Vec<Attempt>>,
+    /// What the processor got done before it failed
+    #[serde(flatten)]
+    output: Option<Value>,
+    elapsed_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archived_path: Option<PathBuf>,
+}
+
+/// The job was still running when the service shut down
+#[derive(Debug, Serialize)]
+struct ProcessorCancelled {
+    success: bool,
+    cancelled: bool,
     message: String,
 }
 
 #[derive(Debug)]
 pub struct NetworkIOProcessor {
     client: Client,
+    /// The endpoint definitions, replaced whenever they are reloaded
+    endpoints: watch::Receiver<Arc<Endpoints>>,
 }
 
+/// A job parsed by its processor, ready to run
+#[derive(Debug)]
 pub struct IO {
-    inner: Inner,
-}
-
-enum Inner {
-    NetworkIO(NetworkIO),
+    processor_id: String,
+    result_path: PathBuf,
+    job: Box<dyn Any + Send>,
 }
 
-#[derive(Debug)]
-struct NetworkIO {
+/// The job of the `com.proxy.network.io` processor
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "NetworkIOBuilder")]
+pub struct NetworkIO {
     method: Method,
-    url: Url,
+    target: Target,
+    query: BTreeMap<String, String>,
     headers: HeaderMap,
     body: Option<String>,
     timeout: Option<Duration>,
-    result_path: PathBuf,
+    body_path: Option<PathBuf>,
+    retry: Option<Retry>,
 }
 
-#[non_exhaustive]
-#[derive(Debug, Deserialize)]
-#[serde(tag = "processor_id")]
-pub enum IOBuilder {
-    #[serde(rename = "com.proxy.network.io")]
-    NetworkIO(NetworkIOBuilder),
+/// Where a network request goes
+#[derive(Debug)]
+enum Target {
+    Url(Url),
+    /// A path below a named endpoint, resolved when the job runs
+    Endpoint {
+        name: String,
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Retry {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    statuses: Vec<StatusCode>,
+    errors: Vec<RetryableError>,
+}
+
+/// A job file whose processor is known, before the processor parses the rest of it
+#[derive(Debug)]
+pub struct IOBuilder {
+    processor_id: String,
+    result_path: PathBuf,
+    /// Relative paths of the job are resolved against it
+    dir: Option<PathBuf>,
+    bytes: Vec<u8>,
 }
 
 type Seconds = u64;
+type Milliseconds = u64;
+
+/// How long an external program or a WebAssembly module may take for a job, unless it says
+/// otherwise
+pub(super) const DEFAULT_TIMEOUT: Seconds = 60;
 
 #[derive(Debug, Deserialize)]
 pub struct NetworkIOBuilder {
     method: String,
-    url: String,
+    /// Either a full `url`, or an `endpoint` and a `path` below it
+    url: Option<String>,
+    endpoint: Option<String>,
+    path: Option<String>,
+    #[serde(default)]
+    query: BTreeMap<String, String>,
+    #[serde(default)]
     headers: Vec<HeaderBuilder>,
     body: Option<String>,
     timeout: Option<Seconds>,
-    result_path: PathBuf,
+    /// Stream the response body to this file instead of embedding it in the result
+    body_path: Option<PathBuf>,
+    retry: Option<RetryBuilder>,
+    /// Read from the envelope, so the body can not overwrite the result
+    result_path: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -78,116 +240,534 @@ struct HeaderBuilder {
     value: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct RetryBuilder {
+    max_attempts: u32,
+    #[serde(default = "RetryBuilder::default_base_delay")]
+    base_delay: Milliseconds,
+    #[serde(default = "RetryBuilder::default_max_delay")]
+    max_delay: Milliseconds,
+    /// Status codes that are worth another attempt
+    #[serde(default = "RetryBuilder::default_statuses")]
+    statuses: Vec<u16>,
+    /// Request errors that are worth another attempt
+    #[serde(default = "RetryBuilder::default_errors")]
+    errors: Vec<RetryableError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RetryableError {
+    Timeout,
+    Connect,
+}
+
 impl Processors {
-    pub fn new(map: HashMap<&'static str, Box<dyn Process>>) -> Self {
-        Self { inner: map }
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the processor for the job files with this `processor_id`, replacing any other
+    pub fn add<P: Process>(&mut self, id: impl Into<String>, processor: P) {
+        self.inner.insert(id.into(), Box::new(processor));
     }
 
-    pub fn add(&mut self, id: &'static str, processor: Box<dyn Process>) {
-        self.inner.insert(id, processor);
+    pub fn contains(&self, id: &str) -> bool {
+        self.inner.contains_key(id)
     }
 
-    pub fn get(&self, id: &'static str) -> Option<&dyn Process> {
-        self.inner.get(id).map(|process| process.as_ref())
+    /// Unregister the processor, return whether there was one
+    pub fn remove(&mut self, id: &str) -> bool {
+        self.inner.remove(id).is_some()
     }
 
-    pub fn remove(&mut self, id: &'static str) -> Option<Box<dyn Process>> {
-        self.inner.remove(id)
+    /// The `processor_id` of every registered processor
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.inner.keys().map(String::as_str)
     }
 
-    pub async fn process(&self, io: IO) -> Result<(), Error> {
-        let processor_id = match io.inner {
-            Inner::NetworkIO(_) => "com.proxy.network.io",
+    fn get(&self, id: &str) -> Result<&dyn ErasedProcess, Error> {
+        self.inner
+            .get(id)
+            .map(|processor| processor.as_ref())
+            .ok_or_else(|| ProcessorNotFound(id.to_owned()))
+    }
+
+    /// Run the job with its processor, the returned report still has to be written
+    pub async fn process(&self, io: IO) -> Result<Report, Error> {
+        let processor = self.get(&io.processor_id)?;
+
+        let result_path = io.result_path;
+
+        let start = Instant::now();
+
+        let document = match processor.process(io.job).await {
+            Ok(output) => {
+                info!("process success: {}", result_path.to_string_lossy());
+
+                ResultDocument::Success(ProcessorSuccess {
+                    success: true,
+                    output,
+                    elapsed_ms: start.elapsed().as_millis(),
+                    archived_path: None,
+                })
+            }
+            Err(error) => {
+                error!("process error: {}", error);
+
+                let message = error.to_string();
+
+                let (attempts, output) = match error {
+                    RequestAttempts { attempts, .. } => (Some(attempts), None),
+                    CommandFailed(output) => (None, Some(to_value(output)?)),
+                    #[cfg(feature = "wasm")]
+                    Error::WasmFailed(output) => (None, Some(to_value(output)?)),
+                    _ => (None, None),
+                };
+
+                ResultDocument::Error(ProcessorError {
+                    success: false,
+                    message,
+                    attempts,
+                    output,
+                    elapsed_ms: start.elapsed().as_millis(),
+                    archived_path: None,
+                })
+            }
         };
 
-        let processor = self
-            .get(processor_id)
-            .ok_or_else(|| ProcessorNotFound(processor_id.to_owned()))?;
+        Ok(Report {
+            result_path,
+            document,
+        })
+    }
+}
+
+#[async_trait]
+impl<P: Process> ErasedProcess for P {
+    fn parse(&self, bytes: &[u8], dir: Option<&Path>) -> Result<Box<dyn Any + Send>, Error> {
+        let mut job: P::Job = from_slice(bytes)?;
+
+        if let Some(dir) = dir {
+            job.relative_to(dir);
+        }
+
+        Ok(Box::new(job))
+    }
+
+    async fn process(&self, job: Box<dyn Any + Send>) -> Result<Map<String, Value>, Error> {
+        let job = job
+            .downcast::<P::Job>()
+            .map_err(|_| InvalidJob("parsed by another processor"))?;
 
-        let result_path = match io.inner {
-            Inner::NetworkIO(ref io) => io.result_path.to_owned(),
+        let Value::Object(output) = to_value(Process::process(self, *job).await?)? else {
+            return Err(InvalidOutput("not a JSON object".into()));
         };
 
-        if let Err(error) = processor.process(io).await {
-            error!("process error: {}", error);
+        // flattened into the result document, so it must not clash with its own keys
+        if let Some(key) = RESERVED_KEYS.iter().find(|key| output.contains_key(**key)) {
+            return Err(InvalidOutput(format!("the key {} is reserved", key)));
+        }
 
-            if let Some(parent) = result_path.parent() {
-                if !parent.exists() {
-                    create_dir_all(parent).await?;
-                }
-            }
+        Ok(output)
+    }
+}
 
-            let mut file = File::create(result_path).await?;
+impl Report {
+    /// The report of a job which did not finish before the service shut down
+    pub fn cancelled(result_path: PathBuf) -> Self {
+        Self {
+            result_path,
+            document: ResultDocument::Cancelled(ProcessorCancelled {
+                success: false,
+                cancelled: true,
+                message: "the service shut down before the job finished, it will be resumed".into(),
+            }),
+        }
+    }
 
-            let error = ProcessorError {
-                message: error.to_string(),
-            };
+    pub fn success(&self) -> bool {
+        matches!(self.document, ResultDocument::Success(_))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.document, ResultDocument::Cancelled(_))
+    }
 
-            let bytes = to_vec(&error)?;
+    pub fn result_path(&self) -> &Path {
+        &self.result_path
+    }
 
-            file.write_all(&bytes).await?;
+    /// Record where the job file was archived
+    pub fn set_archived_path(&mut self, path: PathBuf) {
+        match &mut self.document {
+            ResultDocument::Success(success) => success.archived_path = Some(path),
+            ResultDocument::Error(error) => error.archived_path = Some(path),
+            // cancelled jobs stay where they are
+            ResultDocument::Cancelled(_) => {}
         }
+    }
 
-        Ok(())
+    /// Write the result document to `result_path`
+    pub async fn write(&self) -> Result<(), Error> {
+        write_result(&self.result_path, &to_vec(&self.document)?).await
     }
 }
 
+/// Write the result document to the given path, creating the parent directory if needed
+async fn write_result(result_path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = result_path.parent() {
+        if !parent.exists() {
+            create_dir_all(parent).await?;
+        }
+    }
+
+    let mut file = File::create(result_path).await?;
+
+    file.write_all(bytes).await?;
+
+    Ok(())
+}
+
 impl Default for NetworkIOProcessor {
+    /// Without endpoints, only jobs with a full `url` work
     fn default() -> Self {
-        Self {
-            client: Client::new(),
+        Self::new(watch::channel(Arc::default()).1)
+    }
+}
+
+impl Job for NetworkIO {
+    fn relative_to(&mut self, dir: &Path) {
+        if let Some(body_path) = &mut self.body_path {
+            // joining an absolute path keeps it as it is
+            *body_path = dir.join(&body_path);
         }
     }
 }
 
 #[async_trait]
 impl Process for NetworkIOProcessor {
-    async fn process(&self, io: IO) -> Result<(), Error> {
-        let Inner::NetworkIO(io) = io.inner;
+    type Job = NetworkIO;
+    type Output = NetworkIOOutput;
+
+    async fn process(&self, mut io: NetworkIO) -> Result<NetworkIOOutput, Error> {
+        let body_path = io.body_path.take();
+
+        let (request, retry) = self.request(io)?;
+
+        let (response, attempts) = self.execute(request, retry.as_ref()).await?;
+
+        info!("response: {:#?}", response);
+
+        let status = response.status().as_u16();
+
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| Header {
+                name: name.to_string(),
+                value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            })
+            .collect();
+
+        let (body, body_file) = match body_path {
+            Some(body_path) => (None, Some(stream_body(response, body_path).await?)),
+            None => (Some(response.text().await?), None),
+        };
+
+        Ok(NetworkIOOutput {
+            status,
+            headers,
+            body,
+            body_file,
+            attempts,
+        })
+    }
+}
+
+impl NetworkIOProcessor {
+    /// The `processor_id` of the job files for this processor
+    pub const ID: &'static str = "com.proxy.network.io";
+
+    pub fn new(endpoints: watch::Receiver<Arc<Endpoints>>) -> Self {
+        Self {
+            client: Client::new(),
+            endpoints,
+        }
+    }
+
+    /// Build the request of the job, on top of the defaults of its endpoint
+    fn request(&self, io: NetworkIO) -> Result<(Request, Option<Retry>), Error> {
+        let endpoints = Arc::clone(&self.endpoints.borrow());
+
+        let (mut url, endpoint) = match io.target {
+            Target::Url(url) => (url, None),
+            Target::Endpoint { name, path } => {
+                let endpoint = endpoints.get(&name)?;
+
+                (endpoint.url(&path)?, Some(endpoint))
+            }
+        };
+
+        if !io.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&io.query);
+        }
+
+        let mut request_builder = self.client.request(io.method, url);
+
+        let mut timeout = io.timeout;
+        let mut retry = io.retry;
 
-        let mut request_builder = self.client.request(io.method, io.url);
+        if let Some(endpoint) = endpoint {
+            request_builder = endpoint.apply(request_builder);
 
+            timeout = timeout.or(endpoint.timeout());
+            retry = retry.or_else(|| endpoint.retry().cloned());
+        }
+
+        // the headers of the job replace the ones of the endpoint
         request_builder = request_builder.headers(io.headers);
 
         if let Some(body) = io.body {
             request_builder = request_builder.body(Body::from(body));
         }
 
-        if let Some(timeout) = io.timeout {
+        if let Some(timeout) = timeout {
             request_builder = request_builder.timeout(timeout);
         }
 
-        let request = request_builder.build()?;
+        Ok((request_builder.build()?, retry))
+    }
+
+    /// Send the request, trying again as long as the retry policy allows it
+    async fn execute(
+        &self,
+        request: Request,
+        retry: Option<&Retry>,
+    ) -> Result<(Response, Vec<Attempt>), Error> {
+        let mut attempts = Vec::new();
+        let mut attempt = 1;
 
-        let response = self.client.execute(request).await?;
+        loop {
+            let current = match request.try_clone() {
+                Some(current) => current,
+                // a streaming body can not be replayed, so it is only sent once
+                None => return Ok((self.client.execute(request).await?, attempts)),
+            };
 
-        info!("response: {:#?}", response);
+            let start = Instant::now();
+
+            let result = self.client.execute(current).await;
+
+            let elapsed_ms = start.elapsed().as_millis();
+
+            let delay = match retry {
+                Some(retry) if attempt < retry.max_attempts => match &result {
+                    Ok(response) => retry.delay_for_response(response, attempt),
+                    Err(error) => retry.delay_for_error(error, attempt),
+                },
+                _ => None,
+            };
+
+            attempts.push(Attempt {
+                attempt,
+                status: result
+                    .as_ref()
+                    .ok()
+                    .map(|response| response.status().as_u16()),
+                error: result.as_ref().err().map(|error| error.to_string()),
+                elapsed_ms,
+                delay_ms: delay.map(|delay| delay.as_millis()),
+            });
 
-        Ok(())
+            let Some(delay) = delay else {
+                return match result {
+                    Ok(response) => Ok((response, attempts)),
+                    Err(source) if retry.is_some() => Err(RequestAttempts { source, attempts }),
+                    Err(source) => Err(source.into()),
+                };
+            };
+
+            warn!(
+                "attempt {} failed, retrying in {}ms",
+                attempt,
+                delay.as_millis()
+            );
+
+            sleep(delay).await;
+
+            attempt += 1;
+        }
     }
 }
 
+impl Retry {
+    /// The server may ask for a longer delay with `Retry-After`, but never beyond `max_delay`
+    fn delay_for_response(&self, response: &Response, attempt: u32) -> Option<Duration> {
+        if !self.statuses.contains(&response.status()) {
+            return None;
+        }
+
+        let delay = match retry_after(response) {
+            Some(delay) => delay.min(self.max_delay),
+            None => self.backoff(attempt),
+        };
+
+        Some(delay)
+    }
+
+    fn delay_for_error(&self, error: &reqwest::Error, attempt: u32) -> Option<Duration> {
+        let retryable = self.errors.iter().any(|kind| match kind {
+            RetryableError::Timeout => error.is_timeout(),
+            RetryableError::Connect => error.is_connect(),
+        });
+
+        retryable.then(|| self.backoff(attempt))
+    }
+
+    /// Exponential backoff capped at `max_delay`, with half of it jittered
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+
+        let capped = exponential.min(self.max_delay);
+
+        let half = capped / 2;
+
+        half + thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+/// Read the `Retry-After` header, only the delay-seconds form is supported
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+impl IO {
+    pub fn processor_id(&self) -> &str {
+        &self.processor_id
+    }
+
+    /// The path where the result document of this job is written
+    pub fn result_path(&self) -> &Path {
+        &self.result_path
+    }
+}
+
+/// Stream the response body chunk by chunk to the given path, hashing it on the way
+async fn stream_body(mut response: Response, body_path: PathBuf) -> Result<BodyFile, Error> {
+    if let Some(parent) = body_path.parent() {
+        if !parent.exists() {
+            create_dir_all(parent).await?;
+        }
+    }
+
+    let mut file = File::create(&body_path).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+
+        file.write_all(&chunk).await?;
+    }
+
+    file.flush().await?;
+
+    Ok(BodyFile {
+        path: body_path,
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
 impl IOBuilder {
     // 从json字节流中解析出一个 IOBuilder
     pub fn new(bytes: &[u8]) -> Result<Self, Error> {
-        let builder = from_slice(bytes)?;
+        let envelope: Envelope = from_slice(bytes)?;
 
-        Ok(builder)
+        Ok(Self::from_envelope(envelope, bytes))
     }
 
-    pub fn build(self) -> Result<IO, Error> {
-        let io = match self {
-            IOBuilder::NetworkIO(builder) => Inner::NetworkIO(builder.build()?),
+    /// Like `new`, a job file without a `processor_id` goes to the given processor
+    pub fn with_default_processor(bytes: &[u8], processor: Option<&str>) -> Result<Self, Error> {
+        let Some(processor) = processor else {
+            return Self::new(bytes);
         };
 
-        Ok(IO { inner: io })
+        let envelope: Envelope<Option<String>> = from_slice(bytes)?;
+
+        let envelope = Envelope {
+            processor_id: envelope
+                .processor_id
+                .unwrap_or_else(|| processor.to_owned()),
+            result_path: envelope.result_path,
+        };
+
+        Ok(Self::from_envelope(envelope, bytes))
+    }
+
+    fn from_envelope(envelope: Envelope, bytes: &[u8]) -> Self {
+        Self {
+            processor_id: envelope.processor_id,
+            result_path: envelope.result_path,
+            dir: None,
+            bytes: bytes.to_owned(),
+        }
+    }
+
+    pub fn processor_id(&self) -> &str {
+        &self.processor_id
+    }
+
+    /// Resolve the relative paths of the job against the given directory
+    pub fn relative_to(mut self, dir: &Path) -> Self {
+        // joining an absolute path keeps it as it is
+        self.result_path = dir.join(&self.result_path);
+
+        self.dir = Some(dir.to_owned());
+
+        self
+    }
+
+    /// Let the processor of the job parse the rest of the job file
+    pub fn build(self, processors: &Processors) -> Result<IO, Error> {
+        let job = processors
+            .get(&self.processor_id)?
+            .parse(&self.bytes, self.dir.as_deref())?;
+
+        Ok(IO {
+            processor_id: self.processor_id,
+            result_path: self.result_path,
+            job,
+        })
+    }
+}
+
+impl TryFrom<NetworkIOBuilder> for NetworkIO {
+    type Error = Error;
+
+    fn try_from(builder: NetworkIOBuilder) -> Result<Self, Error> {
+        builder.build()
     }
 }
 
 impl NetworkIOBuilder {
     fn build(self) -> Result<NetworkIO, Error> {
         let method = Method::from_str(&self.method)?;
-        let url = Url::parse(&self.url)?;
+        let target = match (self.url, self.endpoint, self.path) {
+            (Some(url), None, None) => Target::Url(Url::parse(&url)?),
+            (None, Some(name), path) => Target::Endpoint {
+                name,
+                path: path.unwrap_or_default(),
+            },
+            (Some(_), _, _) => return Err(InvalidJob("url and endpoint or path are exclusive")),
+            (None, None, _) => return Err(InvalidJob("url or endpoint is required")),
+        };
         let headers = self.headers.into_iter().try_fold(
             HeaderMap::new(),
             |mut headers, header| -> Result<HeaderMap, Error> {
@@ -202,13 +782,443 @@ impl NetworkIOBuilder {
 
         let timeout = self.timeout.map(Duration::from_secs);
 
+        // both are resolved against the same directory, so comparing them as written is enough
+        if let (Some(body_path), Some(result_path)) = (&self.body_path, &self.result_path) {
+            if same_path(body_path, result_path) {
+                return Err(InvalidJob("body_path and result_path must differ"));
+            }
+        }
+
         Ok(NetworkIO {
             method,
-            url,
+            target,
+            query: self.query,
             headers,
             body: self.body,
             timeout,
-            result_path: self.result_path,
+            body_path: self.body_path,
+            retry: self.retry.map(RetryBuilder::build).transpose()?,
+        })
+    }
+}
+
+/// Whether the paths are the same, ignoring `.` components
+fn same_path(a: &Path, b: &Path) -> bool {
+    let significant = |component: &Component| *component != Component::CurDir;
+
+    a.components()
+        .filter(significant)
+        .eq(b.components().filter(significant))
+}
+
+impl RetryBuilder {
+    fn default_base_delay() -> Milliseconds {
+        500
+    }
+
+    fn default_max_delay() -> Milliseconds {
+        30_000
+    }
+
+    fn default_statuses() -> Vec<u16> {
+        vec![429, 500, 502, 503, 504]
+    }
+
+    fn default_errors() -> Vec<RetryableError> {
+        vec![RetryableError::Timeout, RetryableError::Connect]
+    }
+
+    fn build(self) -> Result<Retry, Error> {
+        let statuses = self
+            .statuses
+            .into_iter()
+            .map(StatusCode::from_u16)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Retry {
+            max_attempts: self.max_attempts,
+            base_delay: Duration::from_millis(self.base_delay),
+            max_delay: Duration::from_millis(self.max_delay),
+            statuses,
+            errors: self.errors,
         })
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::{json, to_value};
+
+    #[test]
+    fn test_processor_success_document() {
+        let success = ProcessorSuccess {
+            success: true,
+            output: to_value(NetworkIOOutput {
+                status: 200,
+                headers: vec![Header {
+                    name: "content-type".into(),
+                    value: "text/plain".into(),
+                }],
+                body: Some("ok".into()),
+                body_file: None,
+                attempts: vec![Attempt {
+                    attempt: 1,
+                    status: Some(200),
+                    error: None,
+                    elapsed_ms: 10,
+                    delay_ms: None,
+                }],
+            })
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .clone(),
+            elapsed_ms: 12,
+            archived_path: Some("listen/processed/job.json".into()),
+        };
+
+        assert_eq!(
+            to_value(success).unwrap(),
+            json!({
+                "success": true,
+                "status": 200,
+                "headers": [{ "name": "content-type", "value": "text/plain" }],
+                "body": "ok",
+                "attempts": [{ "attempt": 1, "status": 200, "elapsed_ms": 10 }],
+                "elapsed_ms": 12,
+                "archived_path": "listen/processed/job.json",
+            })
+        );
+    }
+
+    /// Returns the message of the job, and its path resolved like the paths of the real jobs
+    #[derive(Debug)]
+    pub(crate) struct Echo;
+
+    #[derive(Debug, Deserialize)]
+    pub(crate) struct EchoJob {
+        message: String,
+        path: Option<PathBuf>,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub(crate) struct EchoOutput {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+    }
+
+    impl Job for EchoJob {
+        fn relative_to(&mut self, dir: &Path) {
+            if let Some(path) = &mut self.path {
+                *path = dir.join(&path);
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Process for Echo {
+        type Job = EchoJob;
+        type Output = EchoOutput;
+
+        async fn process(&self, job: EchoJob) -> Result<EchoOutput, Error> {
+            Ok(EchoOutput {
+                message: job.message,
+                path: job.path,
+            })
+        }
+    }
+
+    /// Returns the same output for every job
+    #[derive(Debug)]
+    struct Fixed(Value);
+
+    #[derive(Debug, Deserialize)]
+    struct FixedJob {}
+
+    impl Job for FixedJob {}
+
+    #[async_trait]
+    impl Process for Fixed {
+        type Job = FixedJob;
+        type Output = Value;
+
+        async fn process(&self, _job: FixedJob) -> Result<Value, Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_processor() {
+        let mut processors = Processors::new();
+
+        processors.add("com.example.echo", Echo);
+
+        let job = br#"{ "message": "hello", "path": "echo.txt", "result_path": "result.json" }"#;
+
+        let io = IOBuilder::with_default_processor(job, Some("com.example.echo"))
+            .unwrap()
+            .relative_to(Path::new("results"))
+            .build(&processors)
+            .unwrap();
+
+        assert_eq!(io.processor_id(), "com.example.echo");
+
+        assert_eq!(io.result_path(), Path::new("results/result.json"));
+
+        let report = processors.process(io).await.unwrap();
+
+        assert!(report.success());
+
+        let document = to_value(&report.document).unwrap();
+
+        assert_eq!(document["message"], "hello");
+
+        assert_eq!(document["path"], "results/echo.txt");
+
+        let unknown = br#"{ "processor_id": "com.example.unknown", "result_path": "result.json" }"#;
+
+        let error = IOBuilder::new(unknown)
+            .unwrap()
+            .build(&processors)
+            .unwrap_err();
+
+        assert!(matches!(error, ProcessorNotFound(id) if id == "com.example.unknown"));
+
+        // the errors of the processor specific fields keep their position
+        let invalid = b"{\n  \"processor_id\": \"com.example.echo\",\n  \"result_path\": \"result.json\",\n  \"message\": 1\n}";
+
+        let error = IOBuilder::new(invalid)
+            .unwrap()
+            .build(&processors)
+            .unwrap_err();
+
+        assert!(matches!(error, Error::SerdeJson(error) if error.line() == 4));
+
+        processors.add("com.example.scalar", Fixed(json!(1)));
+        processors.add("com.example.reserved", Fixed(json!({ "success": true })));
+
+        for (id, message) in [
+            ("com.example.scalar", "not a JSON object"),
+            ("com.example.reserved", "the key success is reserved"),
+        ] {
+            let job = format!(
+                r#"{{ "processor_id": "{}", "result_path": "result.json" }}"#,
+                id
+            );
+
+            let io = IOBuilder::new(job.as_bytes())
+                .unwrap()
+                .build(&processors)
+                .unwrap();
+
+            let report = processors.process(io).await.unwrap();
+
+            assert!(!report.success());
+
+            assert_eq!(
+                to_value(&report.document).unwrap()["message"],
+                format!("invalid processor output: {}", message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let retry = RetryBuilder {
+            max_attempts: 5,
+            base_delay: 100,
+            max_delay: 1_000,
+            statuses: RetryBuilder::default_statuses(),
+            errors: RetryBuilder::default_errors(),
+        }
+        .build()
+        .unwrap();
+
+        for (attempt, expected) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1_000),
+            (30, 1_000),
+        ] {
+            let delay = retry.backoff(attempt);
+            let expected = Duration::from_millis(expected);
+
+            assert!(delay >= expected / 2 && delay <= expected);
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let retry = RetryBuilder {
+            max_attempts: 3,
+            base_delay: 100,
+            max_delay: 1_000,
+            statuses: RetryBuilder::default_statuses(),
+            errors: RetryBuilder::default_errors(),
+        }
+        .build()
+        .unwrap();
+
+        let response = |status: u16, retry_after: Option<&str>| {
+            let mut builder = http::Response::builder().status(status);
+
+            if let Some(value) = retry_after {
+                builder = builder.header(RETRY_AFTER, value);
+            }
+
+            Response::from(builder.body("").unwrap())
+        };
+
+        assert_eq!(
+            retry_after(&response(503, Some(" 1 "))),
+            Some(Duration::from_secs(1))
+        );
+
+        // the HTTP date form is not supported
+        assert_eq!(
+            retry_after(&response(503, Some("Wed, 21 Oct 2015 07:28:00 GMT"))),
+            None
+        );
+
+        assert_eq!(retry_after(&response(503, None)), None);
+
+        assert_eq!(
+            retry.delay_for_response(&response(503, Some("0")), 1),
+            Some(Duration::ZERO)
+        );
+
+        // capped at `max_delay`
+        assert_eq!(
+            retry.delay_for_response(&response(429, Some("3600")), 1),
+            Some(Duration::from_secs(1))
+        );
+
+        let delay = retry.delay_for_response(&response(503, None), 2).unwrap();
+
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+
+        assert_eq!(retry.delay_for_response(&response(404, Some("1")), 1), None);
+    }
+
+    /// Answer a request with each of the raw responses in turn, return the URL of the server
+    async fn serve(responses: Vec<&'static str>) -> String {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut request = [0; 1024];
+
+                assert!(stream.read(&mut request).await.unwrap() > 0);
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_retry_attempts() {
+        // unavailable twice, asking for an hour the second time, then answers
+        let url = serve(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 3600\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        ])
+        .await;
+
+        let (_sender, receiver) = watch::channel(Arc::new(Endpoints::default()));
+
+        let processor = NetworkIOProcessor::new(receiver);
+
+        let io: NetworkIO = serde_json::from_str(&format!(
+            r#"{{
+                "method": "GET",
+                "url": "{}",
+                "retry": {{ "max_attempts": 3, "base_delay": 10, "max_delay": 50 }}
+            }}"#,
+            url
+        ))
+        .unwrap();
+
+        let (request, retry) = processor.request(io).unwrap();
+
+        let (response, attempts) = processor.execute(request, retry.as_ref()).await.unwrap();
+
+        assert_eq!(response.status(), 200);
+
+        let statuses: Vec<_> = attempts.iter().map(|attempt| attempt.status).collect();
+
+        assert_eq!(statuses, [Some(503), Some(503), Some(200)]);
+
+        assert!(attempts[0].delay_ms.is_some_and(|delay| delay <= 10));
+        assert_eq!(attempts[1].delay_ms, Some(50));
+        assert_eq!(attempts[2].delay_ms, None);
+    }
+
+    #[tokio::test]
+    async fn test_body_path() {
+        let dir = std::env::current_dir().unwrap().join("test_body_path");
+
+        let url = serve(vec!["HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"]).await;
+
+        let job = |body_path: &str| {
+            format!(
+                r#"{{
+                    "processor_id": "com.proxy.network.io",
+                    "result_path": "result.json",
+                    "method": "GET",
+                    "url": "{}",
+                    "body_path": "{}"
+                }}"#,
+                url, body_path
+            )
+        };
+
+        // the body would overwrite the result
+        for body_path in ["result.json", "./result.json"] {
+            let error = serde_json::from_str::<NetworkIO>(&job(body_path)).unwrap_err();
+
+            assert!(error.to_string().contains("body_path"), "{}", error);
+        }
+
+        let mut io: NetworkIO = serde_json::from_str(&job("bodies/body.txt")).unwrap();
+
+        io.relative_to(&dir);
+
+        let output = Process::process(&NetworkIOProcessor::default(), io)
+            .await
+            .unwrap();
+
+        let body_path = dir.join("bodies/body.txt");
+
+        assert_eq!(
+            tokio::fs::read_to_string(&body_path).await.unwrap(),
+            "hello"
+        );
+
+        assert_eq!(
+            to_value(output).unwrap()["body_file"],
+            json!({
+                "path": body_path,
+                "size": 5,
+                "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            })
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
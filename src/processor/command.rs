@@ -0,0 +1,447 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env::{current_dir, split_paths, var_os},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::Command,
+    time::timeout,
+};
+use tracing::{info, warn};
+
+use super::{Job, Process, Seconds};
+use crate::{
+    error::Error::{self, CommandFailed, CommandNotAllowed, EnvNotAllowed},
+    outcome::CommandOutput,
+};
+
+/// How much of stdout and stderr each is kept, unless the job says otherwise
+pub(super) const DEFAULT_MAX_OUTPUT: usize = 1024 * 1024;
+
+/// Runs a local program, without a shell unless the job asks for one
+#[derive(Debug, Default)]
+pub struct CommandExecProcessor {
+    /// The programs which may run, as written in the jobs, e.g. `convert` or `/opt/bin/import.sh`,
+    /// along with where they were found when the service started
+    allowlist: HashMap<String, PathBuf>,
+    /// Where the shell was found, `None` if shell jobs are not allowed
+    shell: Option<PathBuf>,
+    /// The only variables a program sees, taken from the service unless the job sets them
+    env: Vec<String>,
+}
+
+/// The job of the `com.proxy.command.exec` processor
+#[derive(Debug, Deserialize)]
+pub struct CommandExec {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    /// Set on top of the variables passed through from the service, only the allowed ones
+    #[serde(default)]
+    env: HashMap<String, String>,
+    working_dir: Option<PathBuf>,
+    stdin: Option<String>,
+    timeout: Option<Seconds>,
+    /// Run `program` as a command line of `sh -c`, the args become `$1`, `$2` and so on
+    ///
+    /// On Windows it is a command line of `cmd /C` instead, which has no `$1`: the args are
+    /// appended to the command line, where `cmd` parses them too
+    #[serde(default)]
+    shell: bool,
+    /// How many bytes of stdout and stderr each end up in the result
+    max_output: Option<usize>,
+}
+
+impl CommandExecProcessor {
+    /// The `processor_id` of the job files for this processor
+    pub const ID: &'static str = "com.proxy.command.exec";
+
+    /// Only the programs in the allowlist may run, none if it is empty, and shell jobs only if
+    /// `allow_shell` is set
+    ///
+    /// The programs are looked up in the `PATH` of the service once, here, so a job can not swap
+    /// them for others. The ones which are not found are skipped. The programs see only the
+    /// variables named in `env`, a job may set only those too
+    pub fn new(allowlist: Vec<String>, allow_shell: bool, env: Vec<String>) -> Self {
+        let allowlist = allowlist
+            .into_iter()
+            .filter_map(|program| match resolve(&program) {
+                Some(path) => Some((program, path)),
+                None => {
+                    warn!("allowed program {:?} not found, skipping it", program);
+
+                    None
+                }
+            })
+            .collect();
+
+        let shell = allow_shell.then(|| resolve(SHELL)).flatten();
+
+        if allow_shell && shell.is_none() {
+            warn!("shell {:?} not found, shell jobs can not run", SHELL);
+        }
+
+        Self {
+            allowlist,
+            shell,
+            env,
+        }
+    }
+
+    /// Where the program of the job is, the shell for a shell job
+    fn allow(&self, job: &CommandExec) -> Result<&Path, Error> {
+        if let Some(name) = job.env.keys().find(|name| !self.env_allowed(name)) {
+            return Err(EnvNotAllowed(name.clone()));
+        }
+
+        let (name, program) = if job.shell {
+            (SHELL, self.shell.as_ref())
+        } else {
+            (job.program.as_str(), self.allowlist.get(&job.program))
+        };
+
+        program
+            .map(PathBuf::as_path)
+            .ok_or_else(|| CommandNotAllowed(name.to_owned()))
+    }
+
+    /// Whether the variable is passed to the programs, the names are not case-sensitive on Windows
+    fn env_allowed(&self, name: &str) -> bool {
+        self.env.iter().any(|allowed| {
+            if cfg!(windows) {
+                allowed.eq_ignore_ascii_case(name)
+            } else {
+                allowed == name
+            }
+        })
+    }
+}
+
+/// The absolute path of the program, looked up in `PATH` unless it is a path already
+fn resolve(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+
+    if path.components().count() > 1 || path.is_absolute() {
+        let path = current_dir().ok()?.join(path);
+
+        return path.is_file().then_some(path);
+    }
+
+    split_paths(&var_os("PATH")?)
+        .filter(|dir| dir.is_absolute())
+        .flat_map(|dir| {
+            let path = dir.join(program);
+
+            // `cmd` is `cmd.exe` on disk
+            let exe = cfg!(windows).then(|| path.with_extension("exe"));
+
+            [Some(path), exe].into_iter().flatten()
+        })
+        .find(|path| path.is_file())
+}
+
+#[cfg(not(windows))]
+const SHELL: &str = "sh";
+#[cfg(not(windows))]
+const SHELL_ARG: &str = "-c";
+
+#[cfg(windows)]
+const SHELL: &str = "cmd";
+#[cfg(windows)]
+const SHELL_ARG: &str = "/C";
+
+impl Job for CommandExec {}
+
+#[async_trait]
+impl Process for CommandExecProcessor {
+    type Job = CommandExec;
+    type Output = CommandOutput;
+
+    async fn process(&self, job: CommandExec) -> Result<CommandOutput, Error> {
+        let program = self.allow(&job)?;
+
+        let mut command = Command::new(program);
+
+        if job.shell {
+            command.arg(SHELL_ARG).arg(&job.program);
+
+            // `$0` of the command line is the shell, so the args start at `$1`
+            #[cfg(not(windows))]
+            command.arg(SHELL);
+        }
+
+        // anything else, like `BASH_ENV` or `NODE_OPTIONS`, could make the program run other code
+        command.env_clear();
+
+        for name in &self.env {
+            if let Some(value) = var_os(name) {
+                command.env(name, value);
+            }
+        }
+
+        command.args(&job.args).envs(&job.env);
+
+        if let Some(working_dir) = &job.working_dir {
+            command.current_dir(working_dir);
+        }
+
+        info!("running {:?}", job.program);
+
+        let output = run(
+            command,
+            job.stdin.as_deref().map(str::as_bytes),
+            job.timeout.map(Duration::from_secs),
+            job.max_output.unwrap_or(DEFAULT_MAX_OUTPUT),
+        )
+        .await?;
+
+        if output.succeeded() {
+            Ok(output)
+        } else {
+            Err(CommandFailed(Box::new(output)))
+        }
+    }
+}
+
+/// Run the command, write `stdin` to it and capture at most `max_output` bytes of its output
+///
+/// Only stdin, stdout, stderr and `kill_on_drop` of the command are set here
+pub(super) async fn run(
+    mut command: Command,
+    stdin: Option<&[u8]>,
+    limit: Option<Duration>,
+    max_output: usize,
+) -> Result<CommandOutput, Error> {
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let program = command.as_std().get_program().to_owned();
+
+    let mut child = command.spawn()?;
+
+    let input = child.stdin.take();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let input = async {
+        if let (Some(mut input), Some(stdin)) = (input, stdin) {
+            // a program which does not read its input closes the pipe early
+            if let Err(error) = input.write_all(stdin).await {
+                warn!("failed to write stdin of {:?}: {}", program, error);
+            }
+        }
+    };
+
+    let mut stdout_capped = Capped::default();
+    let mut stderr_capped = Capped::default();
+
+    // the pipes are drained while the program runs, so it never blocks on a full one
+    let run = async {
+        let (_, stdout, stderr, status) = tokio::join!(
+            input,
+            stdout_capped.read(stdout, max_output),
+            stderr_capped.read(stderr, max_output),
+            child.wait(),
+        );
+
+        stdout?;
+        stderr?;
+
+        Ok::<_, Error>(status?)
+    };
+
+    let finished = match limit {
+        Some(limit) => timeout(limit, run).await.ok(),
+        None => Some(run.await),
+    };
+
+    let timed_out = finished.is_none();
+
+    let exit_code = match finished {
+        Some(status) => status?.code(),
+        None => {
+            warn!("{:?} timed out, killing it", program);
+
+            child.kill().await?;
+
+            None
+        }
+    };
+
+    // what was read before the timeout is kept, it tells how far the program got
+    let (stdout, stdout_truncated) = stdout_capped.into_parts();
+    let (stderr, stderr_truncated) = stderr_capped.into_parts();
+
+    Ok(CommandOutput {
+        exit_code,
+        timed_out,
+        stdout,
+        stdout_truncated,
+        stderr,
+        stderr_truncated,
+    })
+}
+
+/// The first bytes of an output, and whether some were dropped
+#[derive(Default)]
+struct Capped {
+    kept: Vec<u8>,
+    truncated: bool,
+}
+
+impl Capped {
+    /// Read everything, keep at most `max` bytes
+    async fn read(
+        &mut self,
+        reader: Option<impl AsyncRead + Unpin>,
+        max: usize,
+    ) -> Result<(), Error> {
+        let Some(mut reader) = reader else {
+            return Ok(());
+        };
+
+        let mut buffer = [0; 8192];
+
+        loop {
+            let read = reader.read(&mut buffer).await?;
+
+            if read == 0 {
+                return Ok(());
+            }
+
+            let room = max.saturating_sub(self.kept.len());
+
+            self.truncated |= read > room;
+
+            self.kept.extend_from_slice(&buffer[..read.min(room)]);
+        }
+    }
+
+    fn into_parts(self) -> (String, bool) {
+        (
+            String::from_utf8_lossy(&self.kept).into_owned(),
+            self.truncated,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::from_value;
+
+    fn job(job: serde_json::Value) -> CommandExec {
+        from_value(job).unwrap()
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_command_exec() {
+        let processor =
+            CommandExecProcessor::new(vec!["cat".into()], true, vec!["GREETING".into()]);
+
+        let output = processor
+            .process(job(serde_json::json!({
+                "program": "cat",
+                "stdin": "hello world",
+                "max_output": 5,
+            })))
+            .await
+            .unwrap();
+
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout, "hello");
+        assert!(output.stdout_truncated);
+
+        // the args are never seen by a shell, unless the job asks for one
+        let error = processor
+            .process(job(serde_json::json!({
+                "program": "echo $1 >&2; exit 3",
+                "args": ["oops"],
+                "shell": true,
+            })))
+            .await
+            .unwrap_err();
+
+        let CommandFailed(output) = error else {
+            panic!("unexpected error: {}", error);
+        };
+
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stderr, "oops\n");
+
+        // the output up to the timeout is kept
+        let error = processor
+            .process(job(serde_json::json!({
+                "program": "echo started; while :; do :; done",
+                "shell": true,
+                "timeout": 1,
+            })))
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(error, CommandFailed(output) if output.timed_out && output.stdout == "started\n")
+        );
+
+        // only the listed variables reach the program
+        let output = processor
+            .process(job(serde_json::json!({
+                "program": "echo \"$GREETING\" \"$HOME\"",
+                "env": { "GREETING": "hello" },
+                "shell": true,
+            })))
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, "hello \n");
+
+        // nor can the job swap the program, or what it loads or runs first
+        for name in [
+            "PATH",
+            "LD_PRELOAD",
+            "DYLD_INSERT_LIBRARIES",
+            "BASH_ENV",
+            "NODE_OPTIONS",
+        ] {
+            let error = processor
+                .process(job(serde_json::json!({
+                    "program": "cat",
+                    "env": { name: "/tmp" },
+                })))
+                .await
+                .unwrap_err();
+
+            assert!(matches!(error, EnvNotAllowed(denied) if denied == name));
+        }
+
+        let error = processor
+            .process(job(serde_json::json!({ "program": "true" })))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, CommandNotAllowed(program) if program == "true"));
+
+        // listing the shell as a program does not allow shell jobs
+        let processor = CommandExecProcessor::new(vec!["sh".into()], false, Vec::new());
+
+        let error = processor
+            .process(job(serde_json::json!({ "program": "true", "shell": true })))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, CommandNotAllowed(program) if program == "sh"));
+    }
+}
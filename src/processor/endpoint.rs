@@ -0,0 +1,327 @@
+//! Named HTTP endpoints for the `com.proxy.network.io` jobs, defined in the `*.toml` files of
+//! `PROCESSOR_DIR_PATH`, so the hosts and secrets stay out of the job files
+//!
+//! ```toml
+//! [endpoints.billing]
+//! base_url = "https://billing.example.com/api/"
+//! headers = { "X-Team" = "reports" }
+//! auth = { type = "bearer", token = "..." }
+//! timeout = 30
+//! retry = { max_attempts = 3 }
+//! ```
+//!
+//! A job then sets `endpoint` and `path` instead of `url`, its own headers, timeout and retry
+//! replace the ones of the endpoint.
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    RequestBuilder, Url,
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug, Formatter},
+    path::Path,
+    time::Duration,
+};
+use tokio::fs::{read_dir, read_to_string};
+use tracing::info;
+
+use super::{Retry, RetryBuilder, Seconds};
+use crate::error::Error::{self, EndpointNotFound, InvalidEndpoints, InvalidJob};
+
+/// The file extension of the endpoint definitions
+const EXTENSION: &str = "toml";
+
+/// Every endpoint defined in the processor directory, by name
+#[derive(Debug, Default)]
+pub struct Endpoints {
+    inner: HashMap<String, Endpoint>,
+}
+
+#[derive(Debug)]
+pub struct Endpoint {
+    base_url: Url,
+    headers: HeaderMap,
+    auth: Option<Auth>,
+    timeout: Option<Duration>,
+    retry: Option<Retry>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Auth {
+    Bearer {
+        token: String,
+    },
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointsFile {
+    #[serde(default)]
+    endpoints: BTreeMap<String, EndpointBuilder>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointBuilder {
+    base_url: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    auth: Option<Auth>,
+    timeout: Option<Seconds>,
+    retry: Option<RetryBuilder>,
+}
+
+impl Endpoints {
+    /// Load the endpoints of every `*.toml` file in the directory
+    ///
+    /// Fail on the first invalid file, or on a name defined twice
+    pub async fn load(dir: &Path) -> Result<Self, Error> {
+        let mut paths = Vec::new();
+
+        let mut entries = read_dir(dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+
+            if Self::is_definition(&path) && path.is_file() {
+                paths.push(path);
+            }
+        }
+
+        paths.sort();
+
+        let mut endpoints = Self::default();
+
+        for path in paths {
+            let invalid = |message: String| InvalidEndpoints {
+                path: path.clone(),
+                message,
+            };
+
+            let file: EndpointsFile = toml::from_str(&read_to_string(&path).await?)
+                .map_err(|error| invalid(error.message().to_owned()))?;
+
+            for (name, builder) in file.endpoints {
+                if endpoints.inner.contains_key(&name) {
+                    return Err(invalid(format!("endpoint {} is defined twice", name)));
+                }
+
+                let endpoint = builder
+                    .build()
+                    .map_err(|error| invalid(format!("endpoint {}: {}", name, error)))?;
+
+                endpoints.inner.insert(name, endpoint);
+            }
+        }
+
+        info!("loaded {} endpoints from {:?}", endpoints.inner.len(), dir);
+
+        Ok(endpoints)
+    }
+
+    /// Whether the file holds endpoint definitions, going by its name
+    pub fn is_definition(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension == EXTENSION)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Endpoint, Error> {
+        self.inner
+            .get(name)
+            .ok_or_else(|| EndpointNotFound(name.to_owned()))
+    }
+
+    /// The names of every endpoint
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.inner.keys().map(String::as_str)
+    }
+}
+
+impl Endpoint {
+    /// The URL of the path below the base URL, it can not leave the base URL of the endpoint
+    pub fn url(&self, path: &str) -> Result<Url, Error> {
+        if path.contains(['?', '#']) || path.split('/').any(|segment| segment == "..") {
+            return Err(InvalidJob("path may not contain `..`, `?` or `#`"));
+        }
+
+        let base = format!("{}/", self.base_url.as_str().trim_end_matches('/'));
+
+        let url = Url::parse(&format!("{}{}", base, path.trim_start_matches('/')))?;
+
+        // the parser resolves encoded dots like `%2e%2e` too
+        if !url.as_str().starts_with(&base) {
+            return Err(InvalidJob("path leaves the base URL of the endpoint"));
+        }
+
+        Ok(url)
+    }
+
+    /// Add the default headers and the credentials of the endpoint to the request
+    pub(super) fn apply(&self, mut request_builder: RequestBuilder) -> RequestBuilder {
+        request_builder = request_builder.headers(self.headers.clone());
+
+        match &self.auth {
+            Some(Auth::Bearer { token }) => request_builder.bearer_auth(token),
+            Some(Auth::Basic { username, password }) => {
+                request_builder.basic_auth(username, password.as_ref())
+            }
+            None => request_builder,
+        }
+    }
+
+    pub(super) fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub(super) fn retry(&self) -> Option<&Retry> {
+        self.retry.as_ref()
+    }
+}
+
+impl EndpointBuilder {
+    fn build(self) -> Result<Endpoint, Error> {
+        let headers = self.headers.into_iter().try_fold(
+            HeaderMap::new(),
+            |mut headers, (name, value)| -> Result<HeaderMap, Error> {
+                headers.insert(
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_bytes(value.as_bytes())?,
+                );
+
+                Ok(headers)
+            },
+        )?;
+
+        Ok(Endpoint {
+            base_url: Url::parse(&self.base_url)?,
+            headers,
+            auth: self.auth,
+            timeout: self.timeout.map(Duration::from_secs),
+            retry: self.retry.map(RetryBuilder::build).transpose()?,
+        })
+    }
+}
+
+/// Keep the credentials out of the logs
+impl Debug for Auth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Bearer { .. } => write!(f, "Bearer(..)"),
+            Auth::Basic { username, .. } => write!(f, "Basic({}, ..)", username),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{NetworkIO, NetworkIOProcessor};
+    use reqwest::header::AUTHORIZATION;
+    use serde_json::from_str;
+    use std::{env::current_dir, sync::Arc};
+    use tokio::{
+        fs::{create_dir_all, remove_dir_all, write},
+        sync::watch,
+    };
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let dir = current_dir().unwrap().join("test_endpoints");
+
+        create_dir_all(&dir).await.unwrap();
+
+        write(
+            dir.join("billing.toml"),
+            r#"
+            [endpoints.billing]
+            base_url = "https://billing.example.com/api/"
+            headers = { "X-Team" = "reports", "Accept" = "text/plain" }
+            auth = { type = "bearer", token = "secret" }
+            timeout = 30
+            "#,
+        )
+        .await
+        .unwrap();
+
+        let endpoints = Endpoints::load(&dir).await.unwrap();
+
+        let (_sender, receiver) = watch::channel(Arc::new(endpoints));
+
+        let processor = NetworkIOProcessor::new(receiver);
+
+        let job: NetworkIO = from_str(
+            r#"{
+                "method": "POST",
+                "endpoint": "billing",
+                "path": "/v1/invoices",
+                "query": { "page": "2" },
+                "headers": [{ "name": "Accept", "value": "application/json" }],
+                "body": "{}"
+            }"#,
+        )
+        .unwrap();
+
+        let (request, _) = processor.request(job).unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "https://billing.example.com/api/v1/invoices?page=2"
+        );
+
+        assert_eq!(request.headers()["x-team"], "reports");
+
+        // the job has the last word
+        assert_eq!(request.headers()["accept"], "application/json");
+
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer secret");
+
+        assert_eq!(request.timeout(), Some(&Duration::from_secs(30)));
+
+        let job: NetworkIO =
+            from_str(r#"{ "method": "GET", "endpoint": "unknown", "path": "/" }"#).unwrap();
+
+        assert!(matches!(processor.request(job), Err(EndpointNotFound(name)) if name == "unknown"));
+
+        let endpoints = processor.endpoints.borrow();
+
+        let billing = endpoints.get("billing").unwrap();
+
+        // the path stays below the base URL
+        for path in [
+            "../../admin",
+            "v1/%2e%2e/%2E%2e/admin",
+            "v1?admin=1",
+            "v1#admin",
+        ] {
+            assert!(matches!(billing.url(path), Err(InvalidJob(_))), "{}", path);
+        }
+
+        assert_eq!(
+            billing.url("v1/./invoices").unwrap().as_str(),
+            "https://billing.example.com/api/v1/invoices"
+        );
+
+        drop(endpoints);
+
+        write(
+            dir.join("other.toml"),
+            "[endpoints.billing]\nbase_url = \"https://other\"\n",
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            Endpoints::load(&dir).await,
+            Err(InvalidEndpoints { .. })
+        ));
+
+        remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+//! Processors which are executables in `PROCESSOR_DIR_PATH`, written in any language
+//!
+//! An executable says which jobs it processes either in a manifest next to it, e.g.
+//! `resize.manifest.json` for `resize`, or by printing the same JSON when run with `--describe`:
+//!
+//! ```json
+//! { "processor_id": "com.example.resize", "timeout": 60 }
+//! ```
+//!
+//! The timeout is in seconds, 60 unless it is set. On Windows a file is an executable if its
+//! extension is one of `PATHEXT`, e.g. `.exe` or `.bat`.
+//!
+//! For every job it gets the whole job file on stdin and prints the result, a JSON object, on
+//! stdout. A non-zero exit code fails the job, its stdout and stderr end up in the result file. It
+//! runs in the `result_path` directory of the watch rule if there is one, so relative paths of the
+//! job resolve the same as for the built-in processors.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{from_slice, from_str, to_vec, Map, Value};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{metadata, read, read_dir},
+    process::Command,
+};
+use tracing::{info, warn};
+
+use super::{
+    command::{run, DEFAULT_MAX_OUTPUT},
+    Job, Process, Seconds, DEFAULT_TIMEOUT,
+};
+use crate::error::Error::{self, CommandFailed, InvalidProcessorOutput};
+
+/// The suffix of the manifest files, appended to the file name of the executable
+const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// The argument which asks an executable to describe itself
+const DESCRIBE_ARG: &str = "--describe";
+
+/// How long an executable may take to describe itself
+const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An executable which processes the jobs of a single `processor_id`
+#[derive(Debug)]
+pub struct ExternalProcessor {
+    program: PathBuf,
+    timeout: Duration,
+    max_output: usize,
+}
+
+/// What an executable says about itself, in its manifest or on `--describe`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Description {
+    processor_id: String,
+    timeout: Option<Seconds>,
+    /// How many bytes of stdout and stderr each are read
+    max_output: Option<usize>,
+}
+
+/// The whole job file, as the executable gets it
+#[derive(Debug, Deserialize)]
+pub struct ExternalJob {
+    #[serde(flatten)]
+    pub(super) document: Map<String, Value>,
+    /// The directory relative paths of the job are resolved against
+    #[serde(skip)]
+    dir: Option<PathBuf>,
+}
+
+/// Find the executables in the directory and ask them which jobs they process
+///
+/// An executable which fails to describe itself is skipped, so a broken one does not stop the
+/// others. Return them sorted by file name, along with their `processor_id`
+pub async fn discover(dir: &Path) -> Result<Vec<(String, ExternalProcessor)>, Error> {
+    let mut programs = Vec::new();
+
+    let mut entries = read_dir(dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+
+        if is_executable(&path).await {
+            programs.push(path);
+        }
+    }
+
+    programs.sort();
+
+    let mut processors = Vec::new();
+
+    for program in programs {
+        match describe(&program).await {
+            Ok(description) => {
+                info!(
+                    "found processor {} at {:?}",
+                    description.processor_id, program
+                );
+
+                processors.push((
+                    description.processor_id,
+                    ExternalProcessor {
+                        program,
+                        timeout: Duration::from_secs(
+                            description.timeout.unwrap_or(DEFAULT_TIMEOUT),
+                        ),
+                        max_output: description.max_output.unwrap_or(DEFAULT_MAX_OUTPUT),
+                    },
+                ));
+            }
+            Err(error) => warn!("skipping processor {:?}: {}", program, error),
+        }
+    }
+
+    Ok(processors)
+}
+
+/// Read the manifest of the executable, or run it with `--describe` if it has none
+async fn describe(program: &Path) -> Result<Description, Error> {
+    let manifest = manifest_path(program);
+
+    if manifest.is_file() {
+        return from_slice(&read(&manifest).await?).map_err(|error| InvalidProcessorOutput {
+            program: manifest,
+            message: error.to_string(),
+        });
+    }
+
+    let mut command = Command::new(program);
+
+    command.arg(DESCRIBE_ARG);
+
+    let output = run(command, None, Some(DESCRIBE_TIMEOUT), DEFAULT_MAX_OUTPUT).await?;
+
+    if !output.succeeded() {
+        return Err(CommandFailed(Box::new(output)));
+    }
+
+    from_str(&output.stdout).map_err(|error| InvalidProcessorOutput {
+        program: program.to_owned(),
+        message: error.to_string(),
+    })
+}
+
+/// The manifest next to the file, e.g. `resize.manifest.json` for `resize`
+pub(super) fn manifest_path(program: &Path) -> PathBuf {
+    let mut manifest = program.as_os_str().to_owned();
+
+    manifest.push(MANIFEST_SUFFIX);
+
+    PathBuf::from(manifest)
+}
+
+#[cfg(unix)]
+async fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    // follows symlinks, so a link to an installed program works too
+    match metadata(path).await {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Windows has no executable bit, it goes by the extensions in `PATHEXT`
+#[cfg(not(unix))]
+async fn is_executable(path: &Path) -> bool {
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+
+    let executable = path.extension().is_some_and(|extension| {
+        extensions
+            .split(';')
+            .filter_map(|known| known.strip_prefix('.'))
+            .any(|known| extension.eq_ignore_ascii_case(known))
+    });
+
+    executable
+        && metadata(path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+}
+
+impl ExternalProcessor {
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+}
+
+impl Job for ExternalJob {
+    fn relative_to(&mut self, dir: &Path) {
+        self.dir = Some(dir.to_owned());
+    }
+}
+
+#[async_trait]
+impl Process for ExternalProcessor {
+    type Job = ExternalJob;
+    type Output = Map<String, Value>;
+
+    async fn process(&self, job: ExternalJob) -> Result<Map<String, Value>, Error> {
+        let mut command = Command::new(&self.program);
+
+        if let Some(dir) = &job.dir {
+            command.current_dir(dir);
+        }
+
+        let input = to_vec(&job.document)?;
+
+        let output = run(command, Some(&input), Some(self.timeout), self.max_output).await?;
+
+        if !output.succeeded() {
+            return Err(CommandFailed(Box::new(output)));
+        }
+
+        if output.stdout_truncated {
+            return Err(InvalidProcessorOutput {
+                program: self.program.clone(),
+                message: format!("more than {} bytes", self.max_output),
+            });
+        }
+
+        from_str(&output.stdout).map_err(|error| InvalidProcessorOutput {
+            program: self.program.clone(),
+            message: error.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::current_dir;
+    use tokio::fs::{create_dir_all, remove_dir_all, set_permissions, write};
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn executable(path: &Path, script: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        write(path, script).await.unwrap();
+
+        set_permissions(path, PermissionsExt::from_mode(0o755))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_external_processor() {
+        let dir = current_dir().unwrap().join("test_external");
+
+        create_dir_all(&dir).await.unwrap();
+
+        // described by its manifest, echoes the job back
+        executable(&dir.join("echo"), "#!/bin/sh\nexec cat\n").await;
+
+        write(
+            dir.join("echo.manifest.json"),
+            r#"{ "processor_id": "com.example.echo" }"#,
+        )
+        .await
+        .unwrap();
+
+        // describes itself, fails every job
+        executable(
+            &dir.join("fail"),
+            "#!/bin/sh\n\
+             [ \"$1\" = --describe ] && echo '{\"processor_id\": \"com.example.fail\"}' && exit\n\
+             echo broken >&2; exit 2\n",
+        )
+        .await;
+
+        executable(&dir.join("broken"), "#!/bin/sh\nexit 1\n").await;
+
+        write(dir.join("endpoints.toml"), "").await.unwrap();
+
+        let processors = discover(&dir).await.unwrap();
+
+        let ids: Vec<_> = processors.iter().map(|(id, _)| id.as_str()).collect();
+
+        assert_eq!(ids, ["com.example.echo", "com.example.fail"]);
+
+        // a job can not hang forever, even if the processor does not set a timeout
+        assert_eq!(
+            processors[0].1.timeout,
+            Duration::from_secs(DEFAULT_TIMEOUT)
+        );
+
+        let job = |json: &str| {
+            let mut job: ExternalJob = from_str(json).unwrap();
+
+            job.relative_to(&dir);
+
+            job
+        };
+
+        let output = processors[0]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.echo", "value": 1 }"#))
+            .await
+            .unwrap();
+
+        assert_eq!(output["value"], 1);
+
+        let error = processors[1]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.fail" }"#))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, CommandFailed(output) if output.stderr == "broken\n"));
+
+        remove_dir_all(&dir).await.unwrap();
+    }
+}
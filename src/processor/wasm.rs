@@ -0,0 +1,758 @@
+//! Sandboxed processors, WebAssembly modules for WASI preview1 in `PROCESSOR_DIR_PATH`
+//!
+//! Only core modules importing `wasi_snapshot_preview1` are supported, not the components of WASI
+//! preview2. A module, e.g. `transform.wasm`, is only loaded along with its manifest
+//! `transform.wasm.manifest.json`, which grants its capabilities:
+//!
+//! ```json
+//! {
+//!     "processor_id": "com.example.transform",
+//!     "timeout": 30,
+//!     "http": ["api.example.com"],
+//!     "max_response": 1048576,
+//!     "dirs": [{ "path": "/data/reports", "guest_path": "/reports", "writable": true }]
+//! }
+//! ```
+//!
+//! Like the external processors, `_start` gets the whole job file on stdin and prints the result,
+//! a JSON object, on stdout. A non-zero exit code fails the job. The module sees no environment,
+//! no sockets and only the `dirs` of its manifest. It may import these host calls from `fbr`:
+//!
+//! - `http_fetch(request: i32, request_len: i32) -> i32` sends the JSON request
+//!   `{ "method", "url", "headers": [{ "name", "value" }], "body" }` to one of the `http` hosts,
+//!   and returns the length of the JSON response `{ "status", "headers", "body" }` or
+//!   `{ "error" }`. Redirects are not followed, they are returned like any other response. The
+//!   request has to finish before the module times out, and its body may be at most
+//!   `max_response` bytes
+//! - `read_response(buffer: i32)` copies the last response into the memory of the module
+
+use async_trait::async_trait;
+use reqwest::{redirect::Policy, Client, Method, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, from_str, json, to_vec, Map, Value};
+use std::{
+    fmt::{self, Debug, Formatter},
+    mem::take,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{read, read_dir},
+    runtime::Handle,
+    task::spawn_blocking,
+};
+use tracing::{info, warn};
+use wasmtime::{
+    Caller, Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    Trap,
+};
+use wasmtime_wasi::{
+    pipe::{MemoryInputPipe, MemoryOutputPipe},
+    preview1::{self, WasiP1Ctx},
+    DirPerms, FilePerms, I32Exit, WasiCtxBuilder,
+};
+
+use super::{
+    command::DEFAULT_MAX_OUTPUT,
+    external::{manifest_path, ExternalJob},
+    Header, HeaderBuilder, Process, Seconds, DEFAULT_TIMEOUT,
+};
+use crate::{
+    error::Error::{self, HostNotAllowed, InvalidProcessorOutput, ResponseTooLarge, WasmFailed},
+    outcome::{CommandOutput, WasmOutput},
+};
+
+/// The file extension of the modules
+const EXTENSION: &str = "wasm";
+
+/// The module the host calls are imported from
+const HOST_MODULE: &str = "fbr";
+
+/// How often the running modules check whether they ran out of time
+const TICK: Duration = Duration::from_millis(100);
+
+/// How much memory a module may grow to, unless its manifest says otherwise
+const DEFAULT_MAX_MEMORY: usize = 128 * 1024 * 1024;
+
+/// How big the body of an `http_fetch` response may be, unless the manifest says otherwise
+const DEFAULT_MAX_RESPONSE: usize = 1024 * 1024;
+
+/// A WebAssembly module which processes the jobs of a single `processor_id`
+#[derive(Clone)]
+pub struct WasmProcessor {
+    program: PathBuf,
+    instance: InstancePre<State>,
+    client: Client,
+    capabilities: Arc<Manifest>,
+}
+
+/// Everything a module is allowed to do, written by whoever installs it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    processor_id: String,
+    timeout: Option<Seconds>,
+    /// How many bytes of stdout and stderr each may be written
+    max_output: Option<usize>,
+    /// How many bytes of memory the module may use
+    max_memory: Option<usize>,
+    /// The hosts `http_fetch` may send requests to
+    #[serde(default)]
+    http: Vec<String>,
+    /// How many bytes the body of an `http_fetch` response may have, it is read outside the
+    /// memory of the module
+    max_response: Option<usize>,
+    #[serde(default)]
+    dirs: Vec<Preopen>,
+}
+
+/// A directory the module may use
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Preopen {
+    path: PathBuf,
+    /// Where the module sees the directory, `path` by default
+    guest_path: Option<String>,
+    #[serde(default)]
+    writable: bool,
+}
+
+/// The state of a single run of a module
+struct State {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+    client: Client,
+    capabilities: Arc<Manifest>,
+    /// When the module times out, the host calls have to finish before
+    deadline: Instant,
+    /// The response of the last `http_fetch`, until the module reads it
+    response: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FetchRequest {
+    #[serde(default = "FetchRequest::default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<HeaderBuilder>,
+    body: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FetchResponse {
+    status: u16,
+    headers: Vec<Header>,
+    body: String,
+}
+
+/// Find the modules in the directory which have a manifest, and compile them
+///
+/// A module which fails to load is skipped, so a broken one does not stop the others. Return
+/// them sorted by file name, along with their `processor_id`
+pub async fn discover(dir: &Path) -> Result<Vec<(String, WasmProcessor)>, Error> {
+    let mut programs = Vec::new();
+
+    let mut entries = read_dir(dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+
+        if path
+            .extension()
+            .is_some_and(|extension| extension == EXTENSION)
+            && path.is_file()
+        {
+            programs.push(path);
+        }
+    }
+
+    if programs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    programs.sort();
+
+    let engine = engine()?;
+
+    let mut processors = Vec::new();
+
+    for program in programs {
+        if !manifest_path(&program).is_file() {
+            warn!("skipping processor {:?} without a manifest", program);
+
+            continue;
+        }
+
+        match WasmProcessor::load(&engine, program.clone()).await {
+            Ok((id, processor)) => {
+                info!("found processor {} at {:?}", id, program);
+
+                processors.push((id, processor));
+            }
+            Err(error) => warn!("skipping processor {:?}: {}", program, error),
+        }
+    }
+
+    Ok(processors)
+}
+
+/// The engine every module runs on, its epoch moves on every tick so modules can time out
+fn engine() -> Result<Engine, Error> {
+    let mut config = Config::new();
+
+    config.epoch_interruption(true);
+
+    let engine = Engine::new(&config)?;
+
+    let weak = engine.weak();
+
+    // stops once every module is gone
+    thread::spawn(move || {
+        while let Some(engine) = weak.upgrade() {
+            engine.increment_epoch();
+
+            drop(engine);
+
+            thread::sleep(TICK);
+        }
+    });
+
+    Ok(engine)
+}
+
+impl WasmProcessor {
+    async fn load(engine: &Engine, program: PathBuf) -> Result<(String, Self), Error> {
+        let manifest = manifest_path(&program);
+
+        let capabilities: Manifest =
+            from_slice(&read(&manifest).await?).map_err(|error| InvalidProcessorOutput {
+                program: manifest,
+                message: error.to_string(),
+            })?;
+
+        let module = Module::new(engine, read(&program).await?)?;
+
+        let mut linker = Linker::new(engine);
+
+        preview1::add_to_linker_sync(&mut linker, |state: &mut State| &mut state.wasi)?;
+
+        linker.func_wrap(HOST_MODULE, "http_fetch", http_fetch)?;
+        linker.func_wrap(HOST_MODULE, "read_response", read_response)?;
+
+        // an unknown import fails here instead of on the first job
+        let instance = linker.instantiate_pre(&module)?;
+
+        // a redirect could lead to a host which is not allowed
+        let client = Client::builder().redirect(Policy::none()).build()?;
+
+        let processor = Self {
+            program,
+            instance,
+            client,
+            capabilities: Arc::new(capabilities),
+        };
+
+        Ok((processor.capabilities.processor_id.clone(), processor))
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// Run `_start` of the module with the input on stdin, on the current thread
+    fn run(&self, input: Vec<u8>) -> Result<WasmOutput, Error> {
+        let capabilities = &self.capabilities;
+
+        let max_output = capabilities.max_output.unwrap_or(DEFAULT_MAX_OUTPUT);
+
+        let stdout = MemoryOutputPipe::new(max_output);
+        let stderr = MemoryOutputPipe::new(max_output);
+
+        let mut wasi = WasiCtxBuilder::new();
+
+        wasi.stdin(MemoryInputPipe::new(input))
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .args(&[self.program.to_string_lossy()]);
+
+        for dir in &capabilities.dirs {
+            let (dir_perms, file_perms) = if dir.writable {
+                (DirPerms::all(), FilePerms::all())
+            } else {
+                (DirPerms::READ, FilePerms::READ)
+            };
+
+            let guest_path = match &dir.guest_path {
+                Some(guest_path) => guest_path.clone(),
+                None => dir.path.to_string_lossy().into_owned(),
+            };
+
+            wasi.preopened_dir(&dir.path, guest_path, dir_perms, file_perms)?;
+        }
+
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(capabilities.max_memory.unwrap_or(DEFAULT_MAX_MEMORY))
+            .build();
+
+        let timeout = Duration::from_secs(capabilities.timeout.unwrap_or(DEFAULT_TIMEOUT));
+
+        let mut store = Store::new(
+            self.instance.module().engine(),
+            State {
+                wasi: wasi.build_p1(),
+                limits,
+                client: self.client.clone(),
+                capabilities: Arc::clone(capabilities),
+                deadline: Instant::now() + timeout,
+                response: Vec::new(),
+            },
+        );
+
+        store.limiter(|state| &mut state.limits);
+
+        store.set_epoch_deadline(timeout.as_millis().div_ceil(TICK.as_millis()) as u64 + 1);
+
+        let result = self.instance.instantiate(&mut store).and_then(|instance| {
+            instance
+                .get_typed_func::<(), ()>(&mut store, "_start")?
+                .call(&mut store, ())
+        });
+
+        let (exit_code, timed_out, trap) = match result {
+            Ok(()) => (Some(0), false, None),
+            Err(error) => match (
+                error.downcast_ref::<I32Exit>(),
+                error.downcast_ref::<Trap>(),
+            ) {
+                (Some(exit), _) => (Some(exit.0), false, None),
+                (_, Some(Trap::Interrupt)) => (None, true, None),
+                _ => (None, false, Some(format!("{:#}", error))),
+            },
+        };
+
+        let output = |pipe: MemoryOutputPipe| {
+            let contents = pipe.contents();
+
+            // writing more than that traps the module
+            let truncated = contents.len() >= max_output;
+
+            (String::from_utf8_lossy(&contents).into_owned(), truncated)
+        };
+
+        let (stdout, stdout_truncated) = output(stdout);
+        let (stderr, stderr_truncated) = output(stderr);
+
+        Ok(WasmOutput {
+            output: CommandOutput {
+                exit_code,
+                timed_out,
+                stdout,
+                stdout_truncated,
+                stderr,
+                stderr_truncated,
+            },
+            trap,
+        })
+    }
+}
+
+#[async_trait]
+impl Process for WasmProcessor {
+    type Job = ExternalJob;
+    type Output = Map<String, Value>;
+
+    async fn process(&self, job: ExternalJob) -> Result<Map<String, Value>, Error> {
+        let input = to_vec(&job.document)?;
+
+        let processor = self.clone();
+
+        // the module runs to the end or to its deadline without yielding
+        let output = spawn_blocking(move || processor.run(input)).await??;
+
+        if !output.output.succeeded() {
+            return Err(WasmFailed(Box::new(output)));
+        }
+
+        from_str(&output.output.stdout).map_err(|error| InvalidProcessorOutput {
+            program: self.program.clone(),
+            message: error.to_string(),
+        })
+    }
+}
+
+impl Debug for WasmProcessor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmProcessor")
+            .field("program", &self.program)
+            .field("capabilities", &self.capabilities)
+            .finish()
+    }
+}
+
+impl FetchRequest {
+    fn default_method() -> String {
+        "GET".into()
+    }
+}
+
+/// The `http_fetch` host call, the response is kept until `read_response`
+fn http_fetch(
+    mut caller: Caller<'_, State>,
+    request: i32,
+    request_len: i32,
+) -> wasmtime::Result<i32> {
+    let request = read_memory(&mut caller, request, request_len)?;
+
+    let state = caller.data_mut();
+
+    let response = match fetch(state, &request) {
+        Ok(response) => to_vec(&response)?,
+        Err(error) => to_vec(&json!({ "error": error.to_string() }))?,
+    };
+
+    let len = response.len() as i32;
+
+    state.response = response;
+
+    Ok(len)
+}
+
+/// The `read_response` host call
+fn read_response(mut caller: Caller<'_, State>, buffer: i32) -> wasmtime::Result<()> {
+    let response = take(&mut caller.data_mut().response);
+
+    memory(&mut caller)?.write(&mut caller, buffer as u32 as usize, &response)?;
+
+    Ok(())
+}
+
+fn memory(caller: &mut Caller<'_, State>) -> wasmtime::Result<wasmtime::Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("the module exports no memory"))
+}
+
+fn read_memory(caller: &mut Caller<'_, State>, offset: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let start = offset as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+
+    // checked before copying, the length comes from the module
+    memory(caller)?
+        .data(&*caller)
+        .get(start..end)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmtime::Error::msg("out of bounds memory access"))
+}
+
+/// Send the request of a module, if its host is one of the allowed ones
+fn fetch(state: &State, request: &[u8]) -> Result<FetchResponse, Error> {
+    let capabilities = &state.capabilities;
+
+    let request: FetchRequest = from_slice(request)?;
+
+    let url = Url::parse(&request.url)?;
+
+    let host = url.host_str().unwrap_or_default();
+
+    if !matches!(url.scheme(), "http" | "https")
+        || !capabilities.http.iter().any(|allowed| allowed == host)
+    {
+        return Err(HostNotAllowed(url.to_string()));
+    }
+
+    let max_response = capabilities.max_response.unwrap_or(DEFAULT_MAX_RESPONSE);
+
+    // the module can not be interrupted while it waits for the host call
+    let mut request_builder = state
+        .client
+        .request(Method::from_str(&request.method)?, url)
+        .timeout(state.deadline.saturating_duration_since(Instant::now()));
+
+    for header in request.headers {
+        request_builder = request_builder.header(header.name, header.value);
+    }
+
+    if let Some(body) = request.body {
+        request_builder = request_builder.body(body);
+    }
+
+    // the module runs on a blocking thread of the runtime
+    Handle::current().block_on(async {
+        let mut response = request_builder.send().await?;
+
+        let status = response.status().as_u16();
+
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| Header {
+                name: name.to_string(),
+                value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            })
+            .collect();
+
+        let mut body = Vec::new();
+
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > max_response {
+                return Err(ResponseTooLarge(max_response));
+            }
+
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(FetchResponse {
+            status,
+            headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::current_dir;
+    use tokio::{
+        fs::{create_dir_all, remove_dir_all, write},
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Copy stdin to stdout
+    const ECHO: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_read"
+                (func $fd_read (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")
+                (block $done
+                    (loop $copy
+                        (i32.store (i32.const 0) (i32.const 64))
+                        (i32.store (i32.const 4) (i32.const 1024))
+                        (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (br_if $done (i32.eqz (i32.load (i32.const 8))))
+                        (i32.store (i32.const 4) (i32.load (i32.const 8)))
+                        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                        (br $copy)))))
+    "#;
+
+    /// Fetch the URL, print the response
+    fn fetch(url: &str) -> String {
+        let request = format!(r#"{{"url": "{}"}}"#, url);
+
+        format!(
+            r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "fbr" "http_fetch" (func $http_fetch (param i32 i32) (result i32)))
+                (import "fbr" "read_response" (func $read_response (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "{}")
+                (func (export "_start")
+                    (i32.store (i32.const 4) (call $http_fetch (i32.const 1024) (i32.const {})))
+                    (call $read_response (i32.const 2048))
+                    (i32.store (i32.const 0) (i32.const 2048))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+            "#,
+            request.replace('"', "\\\""),
+            request.len()
+        )
+    }
+
+    /// Create `out.txt` in the first preopened directory, exit with the error code
+    const CREATE: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 16) "out.txt")
+            (func (export "_start")
+                (call $proc_exit (call $path_open
+                    (i32.const 3) (i32.const 0) (i32.const 16) (i32.const 7)
+                    ;; O_CREAT, with the right to write
+                    (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 8)))))
+    "#;
+
+    const SPIN: &str = r#"(module (func (export "_start") (loop $spin (br $spin))))"#;
+
+    #[tokio::test]
+    async fn test_wasm_processor() {
+        let dir = current_dir().unwrap().join("test_wasm");
+
+        let data = dir.join("data");
+
+        create_dir_all(&data).await.unwrap();
+
+        // answers every request, `/moved` with a redirect to a host which is not allowed
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+
+                let read = stream.read(&mut request).await.unwrap();
+
+                let response = if request[..read].starts_with(b"GET /moved ") {
+                    "HTTP/1.1 302 Found\r\nLocation: http://example.com/\r\nContent-Length: 0\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+                };
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let manifest =
+            |id: &str, extra: &str| format!(r#"{{ "processor_id": "{}"{} }}"#, id, extra);
+
+        let http = r#", "http": ["127.0.0.1"]"#;
+
+        let dirs = |writable: bool| {
+            format!(
+                r#", "dirs": [{{ "path": {:?}, "writable": {} }}]"#,
+                data.to_string_lossy(),
+                writable
+            )
+        };
+
+        // the text format works as well as the binary one
+        for (name, module, manifest) in [
+            ("echo", ECHO.into(), manifest("com.example.echo", "")),
+            (
+                "fetch",
+                fetch("http://example.com/"),
+                manifest("com.example.fetch", ""),
+            ),
+            (
+                "fetch_allowed",
+                fetch(&format!("{}/", url)),
+                manifest("com.example.fetch_allowed", http),
+            ),
+            (
+                "fetch_moved",
+                fetch(&format!("{}/moved", url)),
+                manifest("com.example.fetch_moved", http),
+            ),
+            (
+                "read_only",
+                CREATE.into(),
+                manifest("com.example.read_only", &dirs(false)),
+            ),
+            (
+                "writable",
+                CREATE.into(),
+                manifest("com.example.writable", &dirs(true)),
+            ),
+            (
+                "spin",
+                SPIN.into(),
+                manifest("com.example.spin", r#", "timeout": 1"#),
+            ),
+        ] {
+            write(dir.join(format!("{}.wasm", name)), module)
+                .await
+                .unwrap();
+
+            write(dir.join(format!("{}.wasm.manifest.json", name)), manifest)
+                .await
+                .unwrap();
+        }
+
+        write(dir.join("orphan.wasm"), SPIN).await.unwrap();
+
+        let processors = discover(&dir).await.unwrap();
+
+        let ids: Vec<_> = processors.iter().map(|(id, _)| id.as_str()).collect();
+
+        assert_eq!(
+            ids,
+            [
+                "com.example.echo",
+                "com.example.fetch",
+                "com.example.fetch_allowed",
+                "com.example.fetch_moved",
+                "com.example.read_only",
+                "com.example.spin",
+                "com.example.writable",
+            ]
+        );
+
+        let job = |json: &str| from_str::<ExternalJob>(json).unwrap();
+
+        let output = processors[0]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.echo", "value": 1 }"#))
+            .await
+            .unwrap();
+
+        assert_eq!(output["value"], 1);
+
+        let output = processors[1]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.fetch" }"#))
+            .await
+            .unwrap();
+
+        assert_eq!(output["error"], "host not allowed: http://example.com/");
+
+        let output = processors[2]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.fetch_allowed" }"#))
+            .await
+            .unwrap();
+
+        assert_eq!(output["status"], 200);
+        assert_eq!(output["body"], "hello");
+
+        // the redirect is up to the module
+        let output = processors[3]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.fetch_moved" }"#))
+            .await
+            .unwrap();
+
+        assert_eq!(output["status"], 302);
+
+        let read_only = processors[4].1.clone();
+
+        let output = spawn_blocking(move || read_only.run(Vec::new()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_ne!(output.output.exit_code, Some(0));
+        assert!(!data.join("out.txt").exists());
+
+        let writable = processors[6].1.clone();
+
+        let output = spawn_blocking(move || writable.run(Vec::new()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(output.output.exit_code, Some(0));
+        assert!(data.join("out.txt").is_file());
+
+        let error = processors[5]
+            .1
+            .process(job(r#"{ "processor_id": "com.example.spin" }"#))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, WasmFailed(output) if output.output.timed_out));
+
+        remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,1100 @@
+use futures::{future::try_join_all, Stream};
+use notify::RecommendedWatcher;
+use notify_debouncer_full::{DebounceEventResult, Debouncer, FileIdMap};
+use serde::Serialize;
+use std::{
+    future::pending,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    runtime::Handle,
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        watch,
+    },
+    task::JoinSet,
+    time::{interval, sleep, Interval, MissedTickBehavior},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{Config, WatchRule, WatchRuleBuilder},
+    error::Error::{self, AlreadyRunning, ChannelClosed, JobQueueClosed, NoConfig},
+    events::{next, Events, JobEvent, Sink},
+    file_watcher::{
+        filter_events, ready_marker_path, Backend, FileWatcher, Rescan, WatcherMetrics,
+    },
+    journal::{JobState, Journal},
+    processor::{
+        command::CommandExecProcessor, endpoint::Endpoints, external, NetworkIOProcessor, Process,
+        Processors,
+    },
+    worker::WorkerPool,
+};
+
+#[cfg(feature = "wasm")]
+use crate::processor::wasm;
+
+/// How long to wait before restarting a failed watcher
+const WATCHER_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Debounce the events of the config file, editors often write it in several steps
+const CONFIG_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// The file watching service, cheap to clone so it can be shut down while it runs
+#[derive(Debug, Clone)]
+pub struct Service {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Every job uses the config current at the time it starts
+    configs: watch::Sender<Arc<Config>>,
+    /// The rules built in code, added again to every reloaded config
+    rules: Vec<WatchRuleBuilder>,
+    processors: Arc<Processors>,
+    /// The endpoints of the network jobs, reloaded when their files change
+    endpoints: watch::Sender<Arc<Endpoints>>,
+    sinks: Vec<Arc<dyn Sink>>,
+    events: Arc<Events>,
+    metrics: Arc<WatcherMetrics>,
+    shutdown: watch::Sender<bool>,
+    running: AtomicBool,
+}
+
+/// Marks the service as stopped once `run` ends, also when its future is dropped halfway
+struct Running<'a> {
+    inner: &'a Inner,
+    /// Flushed on drop, unless `serve` got to it already
+    journal: Option<Arc<Journal>>,
+}
+
+/// Puts a service together, with the built-in processors unless told otherwise
+#[derive(Debug)]
+pub struct ServiceBuilder {
+    config: Option<Config>,
+    rules: Vec<WatchRuleBuilder>,
+    processors: Processors,
+    /// Register the built-in processors whose `processor_id` is still free
+    builtins: bool,
+    sinks: Vec<Arc<dyn Sink>>,
+}
+
+/// The counters of the service since it was built, a job state counts the jobs which went into it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub queued: u64,
+    /// How many jobs started running, not how many are running right now
+    pub started: u64,
+    pub cancelled: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    /// How many times a watcher lost events and scanned its directory
+    pub rescans: u64,
+    /// How many times a watch had to be set up again
+    pub rewatches: u64,
+    /// How many watched directories are gone right now, their rules wait for them to come back
+    pub missing_roots: u64,
+}
+
+impl Service {
+    pub fn builder() -> ServiceBuilder {
+        ServiceBuilder::default()
+    }
+
+    /// Watch the directories and process the job files until `shutdown` is called
+    ///
+    /// Return whether every running job finished within the grace period, the others are
+    /// resumed on the next run
+    pub async fn run(&self) -> Result<bool, Error> {
+        if self.inner.running.swap(true, Ordering::SeqCst) {
+            return Err(AlreadyRunning);
+        }
+
+        let mut running = Running {
+            inner: &self.inner,
+            journal: None,
+        };
+
+        self.serve(&mut running).await
+    }
+
+    /// Stop watching, let the running jobs finish within the grace period, then cancel them
+    pub fn shutdown(&self) {
+        self.inner.shutdown.send_replace(true);
+    }
+
+    /// Load the config file and the endpoints again, each on its own: an invalid one is returned
+    /// as an error and the old one keeps running, while the other is still applied
+    pub async fn reload(&self) -> Result<(), Error> {
+        // a new processor directory only applies after a restart
+        let dir = self.config().processor_dir_path().clone();
+
+        let config = self.reload_config().await;
+        let endpoints = self.reload_endpoints(&dir).await;
+
+        if let (Err(_), Err(error)) = (&config, &endpoints) {
+            error!("invalid endpoints, keeping the old ones: {}", error);
+        }
+
+        config.and(endpoints)
+    }
+
+    async fn reload_config(&self) -> Result<(), Error> {
+        let current = self.config();
+
+        let config = current
+            .reload()
+            .await?
+            .with_rules(&self.inner.rules)
+            .await?;
+
+        warn_restart_required(&current, &config);
+
+        self.inner.configs.send_replace(Arc::new(config));
+
+        info!("config reloaded");
+
+        Ok(())
+    }
+
+    async fn reload_endpoints(&self, dir: &Path) -> Result<(), Error> {
+        let endpoints = Endpoints::load(dir).await?;
+
+        self.inner.endpoints.send_replace(Arc::new(endpoints));
+
+        Ok(())
+    }
+
+    /// The config currently in use
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.inner.configs.borrow())
+    }
+
+    /// The job events from now on, a subscriber which falls too far behind misses the oldest ones
+    pub fn events(&self) -> impl Stream<Item = JobEvent> + Send + Unpin + 'static {
+        self.inner.events.subscribe()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let events = &self.inner.events;
+
+        Stats {
+            queued: events.count(JobState::Queued),
+            started: events.count(JobState::Running),
+            cancelled: events.count(JobState::Cancelled),
+            succeeded: events.count(JobState::Succeeded),
+            failed: events.count(JobState::Failed),
+            rescans: self.inner.metrics.rescans(),
+            rewatches: self.inner.metrics.rewatches(),
+            missing_roots: self.inner.metrics.missing_roots(),
+        }
+    }
+
+    async fn serve(&self, running: &mut Running<'_>) -> Result<bool, Error> {
+        let mut shutdown = self.inner.shutdown.subscribe();
+
+        let config = self.config();
+
+        let journal =
+            Arc::new(Journal::open(config.state_path(), config.journal_retention()).await?);
+
+        running.journal = Some(Arc::clone(&journal));
+
+        let mut config_receiver = self.inner.configs.subscribe();
+
+        let (pool, dispatcher) = WorkerPool::new(
+            Arc::clone(&self.inner.processors),
+            Arc::clone(&journal),
+            config_receiver.clone(),
+            Arc::clone(&self.inner.events),
+        );
+
+        let (sinks_done, mut sinks) = self.spawn_sinks();
+
+        let mut tasks = JoinSet::new();
+
+        if config.watch_config() {
+            tasks.spawn(self.clone().watch_config_file());
+        }
+
+        tasks.spawn(self.clone().watch_endpoints());
+
+        // jobs which were queued or running when the service stopped
+        pool.resume(config.ready_marker()).await?;
+
+        loop {
+            let config = Arc::clone(&config_receiver.borrow_and_update());
+
+            select! {
+                _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+                // the sender lives as long as the service, so the channel stays open
+                Ok(()) = config_receiver.changed() => {
+                    info!("config changed, restarting watcher");
+
+                    continue;
+                }
+                result = listen(&config, pool.clone(), &self.inner.metrics) => match result {
+                    // without workers there is nothing left to do
+                    Err(JobQueueClosed) => return Err(JobQueueClosed),
+                    Err(error) => error!("watcher stopped: {}", error),
+                    Ok(()) => {}
+                },
+            }
+
+            warn!(
+                "restarting watcher in {} seconds...",
+                WATCHER_RESTART_DELAY.as_secs()
+            );
+
+            select! {
+                _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+                _ = sleep(WATCHER_RESTART_DELAY) => {}
+            }
+        }
+
+        tasks.abort_all();
+
+        let grace_period = config_receiver.borrow().grace_period();
+
+        // the watcher is dropped by now, so no new jobs come in
+        info!(
+            "shutting down, waiting up to {} seconds for running jobs...",
+            grace_period.as_secs()
+        );
+
+        drop(pool);
+
+        let drained = dispatcher.shutdown(grace_period).await;
+
+        journal.flush().await?;
+
+        running.journal = None;
+
+        // the sinks get the events of the last jobs too
+        sinks_done.send_replace(true);
+
+        while sinks.join_next().await.is_some() {}
+
+        Ok(drained)
+    }
+
+    /// Hand every job event to the sinks, until the returned sender says the service stopped
+    fn spawn_sinks(&self) -> (watch::Sender<bool>, JoinSet<()>) {
+        let (done, _) = watch::channel(false);
+
+        let mut tasks = JoinSet::new();
+
+        for sink in &self.inner.sinks {
+            let sink = Arc::clone(sink);
+            let mut receiver = self.inner.events.receiver();
+            let mut done = done.subscribe();
+
+            tasks.spawn(async move {
+                loop {
+                    // the events already sent are taken first
+                    let event = select! {
+                        biased;
+                        Some(event) = next(&mut receiver) => event,
+                        _ = done.wait_for(|done| *done) => break,
+                    };
+
+                    if let Err(error) = sink.send(&event).await {
+                        error!("sink {:?} failed to take {:?}: {}", sink, event.path, error);
+                    }
+                }
+            });
+        }
+
+        (done, tasks)
+    }
+
+    /// Reload the config whenever the config file changes
+    async fn watch_config_file(self) {
+        let path = self.config().path().clone();
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+
+        let (tx, mut rx) = unbounded_channel();
+
+        let _debouncer = match config_debouncer(dir, tx).await {
+            Ok(debouncer) => debouncer,
+            Err(error) => {
+                error!("failed to watch config file {:?}: {}", path, error);
+
+                return;
+            }
+        };
+
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(events) => {
+                    let changed = events
+                        .iter()
+                        .flat_map(|event| event.paths.iter())
+                        .any(|changed| changed.file_name() == path.file_name());
+
+                    if !changed {
+                        continue;
+                    }
+
+                    info!("config file changed, reloading config");
+                }
+                Err(errors) => {
+                    error!("config file notify errors: {:?}", errors);
+
+                    continue;
+                }
+            }
+
+            if let Err(error) = self.reload().await {
+                error!(
+                    "failed to reload, keeping the old config or endpoints: {}",
+                    error
+                );
+            }
+        }
+    }
+
+    /// Reload the endpoints whenever one of their files in the processor directory changes
+    async fn watch_endpoints(self) {
+        let dir = self.config().processor_dir_path().clone();
+
+        let (tx, mut rx) = unbounded_channel();
+
+        let _debouncer = match config_debouncer(dir.clone(), tx).await {
+            Ok(debouncer) => debouncer,
+            Err(error) => {
+                error!("failed to watch processor directory {:?}: {}", dir, error);
+
+                return;
+            }
+        };
+
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(events) => {
+                    let changed = events
+                        .iter()
+                        .flat_map(|event| event.paths.iter())
+                        .any(|changed| Endpoints::is_definition(changed));
+
+                    if !changed {
+                        continue;
+                    }
+
+                    info!("endpoints changed, reloading them");
+                }
+                Err(errors) => {
+                    error!("processor directory notify errors: {:?}", errors);
+
+                    continue;
+                }
+            }
+
+            if let Err(error) = self.reload_endpoints(&dir).await {
+                error!("invalid endpoints, keeping the old ones: {}", error);
+            }
+        }
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        // a dropped future can not wait for it, so it is left to the runtime
+        if let (Some(journal), Ok(handle)) = (self.journal.take(), Handle::try_current()) {
+            handle.spawn(async move {
+                if let Err(error) = journal.flush().await {
+                    error!("failed to flush journal: {}", error);
+                }
+            });
+        }
+
+        // ready to run again
+        self.inner.shutdown.send_replace(false);
+
+        self.inner.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl ServiceBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+
+        self
+    }
+
+    /// Watch another directory, next to the rules of the config file
+    pub fn rule(mut self, rule: WatchRuleBuilder) -> Self {
+        self.rules.push(rule);
+
+        self
+    }
+
+    /// Register a processor, replacing any other with the same `processor_id`
+    pub fn processor<P: Process>(mut self, id: impl Into<String>, processor: P) -> Self {
+        self.processors.add(id, processor);
+
+        self
+    }
+
+    /// Use these processors instead, without the built-in ones
+    pub fn processors(mut self, processors: Processors) -> Self {
+        self.processors = processors;
+
+        self.builtins = false;
+
+        self
+    }
+
+    /// Hand every job event to the sink
+    pub fn sink<S: Sink>(mut self, sink: S) -> Self {
+        self.sinks.push(Arc::new(sink));
+
+        self
+    }
+
+    /// Register the executables and WebAssembly modules in `PROCESSOR_DIR_PATH` too, see
+    /// [`external`] and `wasm`, and load the endpoints defined there, see [`endpoint`](crate::processor::endpoint)
+    pub async fn build(mut self) -> Result<Service, Error> {
+        let config = self.config.ok_or(NoConfig)?.with_rules(&self.rules).await?;
+
+        let (endpoints, _) = watch::channel(Arc::new(
+            Endpoints::load(config.processor_dir_path()).await?,
+        ));
+
+        if self.builtins {
+            if !self.processors.contains(NetworkIOProcessor::ID) {
+                self.processors.add(
+                    NetworkIOProcessor::ID,
+                    NetworkIOProcessor::new(endpoints.subscribe()),
+                );
+            }
+
+            if !self.processors.contains(CommandExecProcessor::ID) {
+                let processor = CommandExecProcessor::new(
+                    config.command_allowlist().to_vec(),
+                    config.command_allow_shell(),
+                    config.command_env().to_vec(),
+                );
+
+                self.processors.add(CommandExecProcessor::ID, processor);
+            }
+        }
+
+        // the processors registered in code come first
+        for (id, processor) in external::discover(config.processor_dir_path()).await? {
+            let program = processor.program().to_owned();
+
+            add_discovered(&mut self.processors, id, &program, processor);
+        }
+
+        #[cfg(feature = "wasm")]
+        for (id, processor) in wasm::discover(config.processor_dir_path()).await? {
+            let program = processor.program().to_owned();
+
+            add_discovered(&mut self.processors, id, &program, processor);
+        }
+
+        let (configs, _) = watch::channel(Arc::new(config));
+
+        let (shutdown, _) = watch::channel(false);
+
+        Ok(Service {
+            inner: Arc::new(Inner {
+                configs,
+                rules: self.rules,
+                processors: Arc::new(self.processors),
+                endpoints,
+                sinks: self.sinks,
+                events: Arc::new(Events::new()),
+                metrics: Arc::new(WatcherMetrics::default()),
+                shutdown,
+                running: AtomicBool::new(false),
+            }),
+        })
+    }
+}
+
+impl Default for ServiceBuilder {
+    fn default() -> Self {
+        Self {
+            config: None,
+            rules: Vec::new(),
+            processors: Processors::new(),
+            builtins: true,
+            sinks: Vec::new(),
+        }
+    }
+}
+
+/// Watch the directory of the config file, so replacing the file is noticed too
+async fn config_debouncer(
+    dir: PathBuf,
+    sender: UnboundedSender<DebounceEventResult>,
+) -> Result<Debouncer<RecommendedWatcher, FileIdMap>, Error> {
+    FileWatcher::new(dir)
+        .await?
+        .debouncer(CONFIG_DEBOUNCE, sender)
+}
+
+/// Register a processor found in the processor directory, unless its `processor_id` is taken
+fn add_discovered<P: Process>(
+    processors: &mut Processors,
+    id: String,
+    program: &Path,
+    processor: P,
+) {
+    if processors.contains(&id) {
+        warn!(
+            "processor {} is already registered, skipping {:?}",
+            id, program
+        );
+    } else {
+        processors.add(id, processor);
+    }
+}
+
+/// The settings which only take effect after a restart
+fn warn_restart_required(old: &Config, new: &Config) {
+    if old.processor_dir_path() != new.processor_dir_path() {
+        warn!("PROCESSOR_DIR_PATH changed, restart to apply it");
+    }
+
+    if old.state_path() != new.state_path() {
+        warn!("STATE_PATH changed, restart to apply it");
+    }
+
+    if old.max_concurrent_jobs() != new.max_concurrent_jobs() {
+        warn!("MAX_CONCURRENT_JOBS changed, restart to apply it");
+    }
+
+    if old.job_queue_size() != new.job_queue_size() {
+        warn!("JOB_QUEUE_SIZE changed, restart to apply it");
+    }
+
+    if old.journal_retention() != new.journal_retention() {
+        warn!("JOURNAL_RETENTION changed, restart to apply it");
+    }
+
+    if old.watch_config() != new.watch_config() {
+        warn!("WATCH_CONFIG changed, restart to apply it");
+    }
+
+    if old.command_allowlist() != new.command_allowlist() {
+        warn!("COMMAND_ALLOWLIST changed, restart to apply it");
+    }
+
+    if old.command_allow_shell() != new.command_allow_shell() {
+        warn!("COMMAND_ALLOW_SHELL changed, restart to apply it");
+    }
+
+    if old.command_env() != new.command_env() {
+        warn!("COMMAND_ENV changed, restart to apply it");
+    }
+}
+
+/// Watch every rule, until the job queue is closed
+async fn listen(config: &Config, pool: WorkerPool, metrics: &WatcherMetrics) -> Result<(), Error> {
+    try_join_all(
+        config
+            .rules()
+            .iter()
+            .map(|rule| supervise(config, rule, pool.clone(), metrics)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Watch the rule, restart its watcher whenever it stops, without touching the other rules
+async fn supervise(
+    config: &Config,
+    rule: &WatchRule,
+    pool: WorkerPool,
+    metrics: &WatcherMetrics,
+) -> Result<(), Error> {
+    loop {
+        match listen_rule(config, rule, pool.clone(), metrics).await {
+            // without workers there is nothing left to do
+            Err(JobQueueClosed) => return Err(JobQueueClosed),
+            Err(error) => error!("watcher of rule {} stopped: {}", rule.name(), error),
+            Ok(()) => {}
+        }
+
+        warn!(
+            "restarting watcher of rule {} in {} seconds...",
+            rule.name(),
+            WATCHER_RESTART_DELAY.as_secs()
+        );
+
+        sleep(WATCHER_RESTART_DELAY).await;
+    }
+}
+
+async fn listen_rule(
+    config: &Config,
+    rule: &WatchRule,
+    pool: WorkerPool,
+    metrics: &WatcherMetrics,
+) -> Result<(), Error> {
+    let (tx, mut rx) = unbounded_channel();
+
+    let file_watcher = FileWatcher::new(rule.path().to_owned())
+        .await?
+        .recursive(rule.recursive())
+        .max_depth(rule.max_depth());
+
+    // the tx is kept too, so the rx stays open for a polling rule and while the watch is set up again
+    let mut debouncer = match rule.backend() {
+        Backend::Native | Backend::Hybrid => {
+            Some(file_watcher.debouncer(config.debounce(), tx.clone())?)
+        }
+        Backend::Poll => None,
+    };
+
+    let mut poll_interval = match rule.backend() {
+        Backend::Native => None,
+        Backend::Poll | Backend::Hybrid => {
+            let mut poll_interval = interval(rule.poll_interval());
+
+            // the first tick is immediate, the initial scan below takes its place
+            poll_interval.tick().await;
+
+            poll_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            Some(poll_interval)
+        }
+    };
+
+    info!(
+        "watching {:?} for rule {} with the {:?} watcher",
+        rule.path(),
+        rule.name(),
+        rule.backend()
+    );
+
+    // the job files of a nested rule belong to that rule
+    let matches = |path: &Path| {
+        rule.matches(path)
+            && config
+                .rule_for(path)
+                .is_some_and(|owner| owner.name() == rule.name())
+    };
+
+    // a file waiting for its ready marker is polled again, until the marker shows up
+    let ready =
+        |path: &Path| matches(path) && (!config.ready_marker() || ready_marker_path(path).exists());
+
+    let mut poller = file_watcher.poller();
+
+    // files dropped in while the service was down or the watcher restarted, those already
+    // queued are skipped by the pool and those already processed by the workers
+    for path in poller.poll(ready).await? {
+        info!("queue pending path: {:?}", path);
+
+        pool.submit(path).await?;
+    }
+
+    loop {
+        let rescan = select! {
+            res = rx.recv() => match res {
+                Some(Ok(debounced_events)) => {
+                    let rescan = Rescan::of(&debounced_events, rule.path());
+
+                    // the events which did arrive are still good
+                    let paths = filter_events(
+                        debounced_events,
+                        rule.triggers(),
+                        matches,
+                        config.ready_marker(),
+                    );
+
+                    for path in paths {
+                        info!("queue path: {:?}", path);
+
+                        pool.submit(path).await?;
+                    }
+
+                    rescan
+                }
+                Some(Err(errors)) => {
+                    warn!("notify errors: {:?}", errors);
+
+                    Some(Rescan::Errors)
+                }
+                None => break,
+            },
+            _ = tick(&mut poll_interval) => {
+                if !rule.path().is_dir() {
+                    Some(Rescan::RootRemoved)
+                } else {
+                    match poller.poll(ready).await {
+                        Ok(paths) => {
+                            for path in paths {
+                                info!("queue polled path: {:?}", path);
+
+                                pool.submit(path).await?;
+                            }
+
+                            None
+                        }
+                        Err(error) => {
+                            warn!("failed to poll {:?}: {}", rule.path(), error);
+
+                            Some(Rescan::Errors)
+                        }
+                    }
+                }
+            }
+        };
+
+        let Some(rescan) = rescan else {
+            continue;
+        };
+
+        metrics.record(rule.name(), rescan);
+
+        let mut rewatch = rescan.rewatch();
+        let mut renew = false;
+
+        // the directory may be replaced again meanwhile, so this is retried until it works
+        let files = loop {
+            // the old watch may still hold on to the removed directory
+            if rewatch {
+                renew |= debouncer.take().is_some();
+            }
+
+            let recovered = async {
+                if rewatch && !rule.path().is_dir() {
+                    let _missing = metrics.missing_root(rule.name(), rule.path());
+
+                    file_watcher.wait_for_root(rule.poll_interval()).await;
+                }
+
+                if renew {
+                    debouncer = Some(file_watcher.debouncer(config.debounce(), tx.clone())?);
+                }
+
+                // forget what was seen before, so every job file left is reconciled
+                poller = file_watcher.poller();
+
+                poller.poll(ready).await
+            };
+
+            match recovered.await {
+                Ok(files) => break files,
+                Err(error) => {
+                    warn!(
+                        "failed to rescan {:?} for rule {}, retrying in {} ms: {}",
+                        rule.path(),
+                        rule.name(),
+                        rule.poll_interval().as_millis(),
+                        error
+                    );
+
+                    rewatch = true;
+
+                    sleep(rule.poll_interval()).await;
+                }
+            }
+        };
+
+        if rewatch {
+            info!("watching {:?} for rule {} again", rule.path(), rule.name());
+        }
+
+        pool.reconcile(files, |path| {
+            config
+                .rule_for(path)
+                .is_some_and(|owner| owner.name() == rule.name())
+        })
+        .await?;
+    }
+
+    error!("event channel closed");
+
+    Err(ChannelClosed("event channel".into()))
+}
+
+/// Resolve on the next tick of the poll interval, never for the native watcher
+async fn tick(poll_interval: &mut Option<Interval>) {
+    match poll_interval {
+        Some(poll_interval) => {
+            poll_interval.tick().await;
+        }
+        None => pending::<()>().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use std::{env::current_dir, sync::Mutex};
+    use tokio::{
+        fs::{create_dir_all, read_to_string, remove_dir_all, write},
+        time::timeout,
+    };
+
+    use crate::processor::tests::Echo;
+
+    #[derive(Debug, Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<JobState>>>);
+
+    #[async_trait]
+    impl Sink for Collect {
+        async fn send(&self, event: &JobEvent) -> Result<(), Error> {
+            self.0.lock().unwrap().push(event.state);
+
+            Ok(())
+        }
+    }
+
+    async fn succeeded(events: &mut (impl Stream<Item = JobEvent> + Unpin)) -> JobEvent {
+        timeout(Duration::from_secs(10), async {
+            while let Some(event) = events.next().await {
+                if event.state == JobState::Succeeded {
+                    return event;
+                }
+            }
+
+            panic!("job events ended");
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_service() {
+        let path = current_dir().unwrap().join("test_service");
+
+        let _ = remove_dir_all(&path).await;
+
+        create_dir_all(path.join("echo")).await.unwrap();
+
+        let config_path = path.join("config.toml");
+
+        let config = format!(
+            r#"
+            listen_path = "{}"
+            processor_dir_path = "{}"
+            whitelist = ["*.json"]
+            stable_period_ms = 0
+        "#,
+            path.join("listen").to_string_lossy(),
+            path.join("processor").to_string_lossy(),
+        );
+
+        write(&config_path, &config).await.unwrap();
+
+        let job = path.join("echo/job.json");
+        let result_path = path.join("result.json");
+
+        let contents = format!(
+            r#"{{ "message": "hello", "result_path": "{}" }}"#,
+            result_path.to_string_lossy()
+        );
+
+        // dropped in before the service runs, so the initial scan finds it
+        write(&job, &contents).await.unwrap();
+
+        let sink = Collect::default();
+
+        let service = Service::builder()
+            .config(Config::load(config_path.clone()).await.unwrap())
+            .processor("com.example.echo", Echo)
+            .rule(
+                WatchRule::builder("echo", path.join("echo"))
+                    .include(["*.json"])
+                    .processor("com.example.echo"),
+            )
+            .sink(sink.clone())
+            .build()
+            .await
+            .unwrap();
+
+        let mut events = service.events();
+
+        let running = tokio::spawn({
+            let service = service.clone();
+
+            async move { service.run().await }
+        });
+
+        assert_eq!(succeeded(&mut events).await.path, job);
+
+        // an identical copy is archived without running it again
+        write(&job, &contents).await.unwrap();
+
+        assert_eq!(succeeded(&mut events).await.path, job);
+
+        assert!(!job.exists());
+
+        assert!(matches!(service.run().await, Err(AlreadyRunning)));
+
+        service.shutdown();
+
+        assert!(running.await.unwrap().unwrap());
+
+        let stats = service.stats();
+
+        assert_eq!((stats.queued, stats.started, stats.succeeded), (2, 1, 2));
+
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            [
+                JobState::Queued,
+                JobState::Running,
+                JobState::Succeeded,
+                JobState::Queued,
+                JobState::Succeeded
+            ]
+        );
+
+        let result: serde_json::Value =
+            serde_json::from_str(&read_to_string(&result_path).await.unwrap()).unwrap();
+
+        assert_eq!(result["message"], "hello");
+
+        // written once the job file is archived
+        assert!(Path::new(result["archived_path"].as_str().unwrap()).is_file());
+
+        // a broken endpoints file does not hold back a valid config
+        write(&config_path, format!("{}debounce_ms = 50\n", config))
+            .await
+            .unwrap();
+
+        write(path.join("processor/broken.toml"), "[endpoints")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            service.reload().await,
+            Err(Error::InvalidEndpoints { .. })
+        ));
+
+        assert_eq!(service.config().debounce(), Duration::from_millis(50));
+
+        remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_rewatch() {
+        let path = current_dir().unwrap().join("test_rewatch");
+
+        let _ = remove_dir_all(&path).await;
+
+        let listen = path.join("listen");
+        let result_path = path.join("result.json");
+
+        create_dir_all(&path).await.unwrap();
+
+        let config_path = path.join("config.toml");
+
+        write(
+            &config_path,
+            format!(
+                r#"
+                listen_path = "{}"
+                processor_dir_path = "{}"
+                whitelist = ["*.json"]
+                stable_period_ms = 0
+                poll_interval_ms = 100
+                state_path = "{}"
+                "#,
+                listen.to_string_lossy(),
+                path.join("processor").to_string_lossy(),
+                path.join("state").to_string_lossy(),
+            ),
+        )
+        .await
+        .unwrap();
+
+        let service = Service::builder()
+            .config(Config::load(config_path).await.unwrap())
+            .processor("com.example.echo", Echo)
+            .build()
+            .await
+            .unwrap();
+
+        let mut events = service.events();
+
+        let running = tokio::spawn({
+            let service = service.clone();
+
+            async move { service.run().await }
+        });
+
+        // let the watcher set up its watch first
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        remove_dir_all(&listen).await.unwrap();
+
+        timeout(Duration::from_secs(10), async {
+            while service.stats().missing_roots == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        create_dir_all(&listen).await.unwrap();
+
+        let job = listen.join("job.json");
+
+        write(
+            &job,
+            format!(
+                r#"{{ "processor_id": "com.example.echo", "message": "back", "result_path": "{}" }}"#,
+                result_path.to_string_lossy()
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(succeeded(&mut events).await.path, job);
+
+        let stats = service.stats();
+
+        assert!(stats.rewatches >= 1);
+
+        assert_eq!(stats.missing_roots, 0);
+
+        service.shutdown();
+
+        assert!(running.await.unwrap().unwrap());
+
+        // dropped halfway, the run still lets go of the service
+        assert!(timeout(Duration::from_millis(200), service.run())
+            .await
+            .is_err());
+
+        let running = tokio::spawn({
+            let service = service.clone();
+
+            async move { service.run().await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        service.shutdown();
+
+        assert!(running.await.unwrap().unwrap());
+
+        remove_dir_all(path).await.unwrap();
+    }
+}
@@ -0,0 +1,764 @@
+use std::{
+    collections::HashMap,
+    future::pending,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    fs::{read, remove_file},
+    select,
+    sync::{
+        mpsc::{channel, error::TrySendError, Receiver, Sender},
+        watch, OwnedSemaphorePermit, Semaphore,
+    },
+    task::{JoinHandle, JoinSet},
+    time::timeout,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    archive::{move_file, Archive},
+    config::{Config, WatchRule},
+    dead_letter::write_dead_letter,
+    error::Error::{self, JobQueueClosed},
+    events::Events,
+    file_watcher::{ready_marker_path, wait_until_stable},
+    journal::{content_hash, JobState, Journal},
+    processor::{IOBuilder, Processors, Report},
+};
+
+/// A pool of workers which processes job files concurrently, up to a limit
+#[derive(Debug, Clone)]
+pub struct WorkerPool {
+    sender: Sender<PathBuf>,
+    journal: Arc<Journal>,
+    events: Arc<Events>,
+    /// The job files which are queued or running, so they are never queued twice, along with
+    /// whether they were submitted again meanwhile
+    active: Arc<Mutex<HashMap<PathBuf, bool>>>,
+}
+
+/// Controls the dispatcher task, used to shut the pool down
+#[derive(Debug)]
+pub struct Dispatcher {
+    handle: JoinHandle<()>,
+    /// Stop dispatching queued jobs
+    stop: watch::Sender<bool>,
+    /// Cancel the running jobs
+    cancel: watch::Sender<bool>,
+}
+
+/// Everything a worker needs to handle a job file
+#[derive(Debug)]
+struct Worker {
+    processors: Arc<Processors>,
+    journal: Arc<Journal>,
+    events: Arc<Events>,
+    active: Arc<Mutex<HashMap<PathBuf, bool>>>,
+    /// Where the job files which were submitted again while they ran are queued once more
+    queue: Sender<PathBuf>,
+    /// The concurrency limit of every watch rule and its slots, by name
+    rule_slots: Mutex<HashMap<String, (usize, Arc<Semaphore>)>>,
+    /// Every job uses the config current at the time it starts
+    config: watch::Receiver<Arc<Config>>,
+    cancel: watch::Receiver<bool>,
+}
+
+impl WorkerPool {
+    /// Spawn the dispatcher, return the pool and the dispatcher
+    ///
+    /// The size of the queue and the concurrency limits are taken from the initial config
+    pub fn new(
+        processors: Arc<Processors>,
+        journal: Arc<Journal>,
+        config: watch::Receiver<Arc<Config>>,
+        events: Arc<Events>,
+    ) -> (Self, Dispatcher) {
+        let (job_queue_size, max_concurrent_jobs) = {
+            let config = config.borrow();
+
+            (config.job_queue_size(), config.max_concurrent_jobs())
+        };
+
+        let (sender, receiver) = channel(job_queue_size);
+
+        let semaphore = Arc::new(Semaphore::new(max_concurrent_jobs));
+
+        // the jobs taken from the queue which wait for their rule or for a free slot
+        let waiting = Arc::new(Semaphore::new(job_queue_size));
+
+        let active = Arc::new(Mutex::new(HashMap::new()));
+
+        let (stop, stop_receiver) = watch::channel(false);
+
+        let (cancel, cancel_receiver) = watch::channel(false);
+
+        let worker = Worker {
+            processors,
+            journal: Arc::clone(&journal),
+            events: Arc::clone(&events),
+            active: Arc::clone(&active),
+            queue: sender.clone(),
+            rule_slots: Mutex::new(HashMap::new()),
+            config,
+            cancel: cancel_receiver,
+        };
+
+        let handle = tokio::spawn(dispatch(
+            receiver,
+            Arc::new(worker),
+            semaphore,
+            waiting,
+            stop_receiver,
+        ));
+
+        let dispatcher = Dispatcher {
+            handle,
+            stop,
+            cancel,
+        };
+
+        let pool = Self {
+            sender,
+            journal,
+            events,
+            active,
+        };
+
+        (pool, dispatcher)
+    }
+
+    /// Queue a job file, waiting for a free slot if the queue is full
+    ///
+    /// A job file which is queued or running already is queued again once it is done, if a new
+    /// file was dropped at its path meanwhile
+    pub async fn submit(&self, path: PathBuf) -> Result<(), Error> {
+        {
+            let mut active = lock(&self.active);
+
+            if let Some(again) = active.get_mut(&path) {
+                debug!("{:?} is already queued", path);
+
+                *again = true;
+
+                return Ok(());
+            }
+
+            active.insert(path.clone(), false);
+        }
+
+        record(&self.journal, &self.events, &path, None, JobState::Queued).await;
+
+        let path = match self.sender.try_send(path) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(path)) => path,
+            Err(TrySendError::Closed(_)) => return Err(JobQueueClosed),
+        };
+
+        warn!(
+            "job queue is full ({} jobs), waiting to queue {:?}",
+            self.sender.max_capacity(),
+            path
+        );
+
+        self.sender.send(path).await.map_err(|_| JobQueueClosed)
+    }
+
+    /// Queue the jobs which were queued or running when the service stopped
+    pub async fn resume(&self, ready_marker: bool) -> Result<(), Error> {
+        for path in self.journal.pending().await {
+            // gone while the service was down, nothing left to resume
+            if !path.is_file() {
+                record(&self.journal, &self.events, &path, None, JobState::Failed).await;
+
+                continue;
+            }
+
+            if ready_marker && !ready_marker_path(&path).exists() {
+                continue;
+            }
+
+            info!("queue pending path: {:?}", path);
+
+            self.submit(path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Bring the journal in line with the job files found by a full scan, after events were lost
+    ///
+    /// Pending jobs of the scope whose files are gone failed, the files found are queued again,
+    /// those already queued are skipped here and those already processed by the workers
+    pub async fn reconcile(
+        &self,
+        files: Vec<PathBuf>,
+        in_scope: impl Fn(&Path) -> bool,
+    ) -> Result<(), Error> {
+        for path in self.journal.pending().await {
+            if !in_scope(&path) || path.is_file() || lock(&self.active).contains_key(&path) {
+                continue;
+            }
+
+            warn!("{:?} disappeared while events were lost", path);
+
+            record(&self.journal, &self.events, &path, None, JobState::Failed).await;
+        }
+
+        for path in files {
+            info!("queue rescanned path: {:?}", path);
+
+            self.submit(path).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Dispatcher {
+    /// Stop dispatching, let the running jobs finish within the grace period, then cancel them
+    ///
+    /// Return whether every running job finished in time. Queued jobs stay in the journal
+    pub async fn shutdown(mut self, grace_period: Duration) -> bool {
+        self.stop.send_replace(true);
+
+        if timeout(grace_period, &mut self.handle).await.is_ok() {
+            info!("all running jobs finished");
+
+            return true;
+        }
+
+        warn!(
+            "jobs still running after {} seconds, cancelling them",
+            grace_period.as_secs()
+        );
+
+        self.cancel.send_replace(true);
+
+        if let Err(error) = self.handle.await {
+            error!("dispatcher failed: {}", error);
+        }
+
+        false
+    }
+}
+
+/// Take the jobs from the queue, each one runs once its rule and the pool have a free slot
+///
+/// A busy rule never holds up the jobs of the other rules
+async fn dispatch(
+    mut receiver: Receiver<PathBuf>,
+    worker: Arc<Worker>,
+    semaphore: Arc<Semaphore>,
+    waiting: Arc<Semaphore>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut tasks = JoinSet::new();
+
+    loop {
+        // the queue only fills up once enough jobs are waiting already
+        let waiting = select! {
+            biased;
+            _ = stop.wait_for(|stop| *stop) => break,
+            // forget about finished jobs
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => continue,
+            // the semaphore is never closed
+            permit = Arc::clone(&waiting).acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => break,
+            },
+        };
+
+        let path = select! {
+            biased;
+            _ = stop.wait_for(|stop| *stop) => break,
+            path = receiver.recv() => match path {
+                Some(path) => path,
+                None => break,
+            },
+        };
+
+        let worker = Arc::clone(&worker);
+        let semaphore = Arc::clone(&semaphore);
+        let mut stop = stop.clone();
+
+        tasks.spawn(async move {
+            let permits = select! {
+                biased;
+                // not started yet, so it is still queued in the journal
+                _ = stop.wait_for(|stop| *stop) => None,
+                permits = worker.acquire(&path, semaphore) => Some(permits),
+            };
+
+            drop(waiting);
+
+            if let Some(_permits) = permits {
+                worker.handle(&path).await;
+            }
+
+            worker.release(path, &mut stop).await;
+        });
+    }
+
+    while tasks.join_next().await.is_some() {}
+}
+
+impl Worker {
+    /// Wait for a free slot of the rule of the job file, then for a free slot of the pool
+    async fn acquire(&self, path: &Path, semaphore: Arc<Semaphore>) -> Vec<OwnedSemaphorePermit> {
+        let config = Arc::clone(&self.config.borrow());
+
+        let mut permits = Vec::with_capacity(2);
+
+        if let Some(rule) = config.rule_for(path) {
+            let rule_slots = {
+                let mut rule_slots = lock(&self.rule_slots);
+
+                // forget the rules removed by a reload
+                rule_slots.retain(|name, _| config.rules().iter().any(|rule| rule.name() == name));
+
+                let limit = rule.max_concurrent_jobs();
+
+                let (current, slots) = rule_slots
+                    .entry(rule.name().to_owned())
+                    .or_insert_with(|| (limit, Arc::new(Semaphore::new(limit))));
+
+                // the limit changed on a reload, the jobs running already keep their old slots
+                if *current != limit {
+                    (*current, *slots) = (limit, Arc::new(Semaphore::new(limit)));
+                }
+
+                Arc::clone(slots)
+            };
+
+            // the semaphores are never closed
+            permits.extend(rule_slots.acquire_owned().await.ok());
+        }
+
+        permits.extend(semaphore.acquire_owned().await.ok());
+
+        permits
+    }
+
+    async fn handle(&self, path: &Path) {
+        info!("path: {:?}", path);
+
+        let config = Arc::clone(&self.config.borrow());
+
+        // the rule was removed since, the job is resumed on the next start or once the rule is
+        // back and its directory is scanned
+        let Some(rule) = config.rule_for(path).cloned() else {
+            warn!("no watch rule for {:?}, leaving it in place", path);
+
+            record(&self.journal, &self.events, path, None, JobState::Cancelled).await;
+
+            return;
+        };
+
+        // handled by an earlier copy of the job, e.g. queued again by a rescan, or removed
+        if !path.is_file() {
+            info!("{:?} is gone, skipping", path);
+
+            record(&self.journal, &self.events, path, None, JobState::Failed).await;
+
+            return;
+        }
+
+        let archive = Archive::new(rule.archive_path().to_owned(), config.archive_rename());
+
+        let prepared = select! {
+            prepared = self.prepare(path, config.stable_period()) => prepared,
+            // not started yet, so it is still queued in the journal
+            _ = self.cancelled() => return,
+        };
+
+        let (hash, result) = match prepared {
+            Ok(bytes) => {
+                let hash = content_hash(&bytes);
+
+                if self.journal.has_succeeded(path, &hash).await {
+                    info!("{:?} was already processed, archiving it", path);
+
+                    // left in place, every rescan would queue it again
+                    if let Err(error) = archive.archive(path, true).await {
+                        error!("failed to archive {:?}: {}", path, error);
+                    }
+
+                    record(
+                        &self.journal,
+                        &self.events,
+                        path,
+                        Some(&hash),
+                        JobState::Succeeded,
+                    )
+                    .await;
+
+                    remove_ready_marker(path, config.ready_marker()).await;
+
+                    return;
+                }
+
+                record(
+                    &self.journal,
+                    &self.events,
+                    path,
+                    Some(&hash),
+                    JobState::Running,
+                )
+                .await;
+
+                let result = self.run(path, &bytes, &rule, &archive).await;
+
+                (Some(hash), result)
+            }
+            Err(error) => (None, Err(error)),
+        };
+
+        let state = match result {
+            Ok(state) => state,
+            // a failing job must never stop the other jobs, so every error ends up here
+            Err(error) => {
+                error!("job {:?} failed: {}", path, error);
+
+                if let Err(error) = write_dead_letter(rule.dead_letter_path(), path, &error).await {
+                    error!("failed to write dead letter for {:?}: {}", path, error);
+                }
+
+                // whether it could not be read, parsed or processed, the job file ends up in
+                // `failed/`, the dead-letter directory only has a copy
+                if path.exists() {
+                    if let Err(error) = archive.archive(path, false).await {
+                        error!("failed to archive {:?}: {}", path, error);
+                    }
+                }
+
+                JobState::Failed
+            }
+        };
+
+        record(&self.journal, &self.events, path, hash.as_deref(), state).await;
+
+        // a cancelled job is resumed on the next start, so it keeps its marker
+        if state != JobState::Cancelled {
+            remove_ready_marker(path, config.ready_marker()).await;
+        }
+    }
+
+    /// Wait for the job file to be completely written, then read it
+    async fn prepare(&self, path: &Path, stable_period: Duration) -> Result<Vec<u8>, Error> {
+        if !stable_period.is_zero() {
+            wait_until_stable(path, stable_period).await?;
+        }
+
+        Ok(read(path).await?)
+    }
+
+    /// Parse and process a single job file, return the state it ended in
+    async fn run(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        rule: &WatchRule,
+        archive: &Archive,
+    ) -> Result<JobState, Error> {
+        let mut builder = IOBuilder::with_default_processor(bytes, rule.processor())?;
+
+        if let Some(result_path) = rule.result_path() {
+            builder = builder.relative_to(result_path);
+        }
+
+        let io = builder.build(&self.processors)?;
+
+        let result_path = io.result_path().to_owned();
+
+        let mut report = select! {
+            report = self.processors.process(io) => report?,
+            _ = self.cancelled() => Report::cancelled(result_path),
+        };
+
+        if report.is_cancelled() {
+            warn!("job {:?} cancelled", path);
+
+            report.write().await?;
+
+            return Ok(JobState::Cancelled);
+        }
+
+        let destination = archive.destination(path, report.success()).await?;
+
+        // a job file left in place runs again, so its success is never reported
+        if let Err(error) = move_file(path, &destination).await {
+            if !report.success() {
+                report.write().await?;
+            }
+
+            return Err(error);
+        }
+
+        // the result only points at the archived file once it is there
+        report.set_archived_path(destination);
+
+        report.write().await?;
+
+        if report.success() {
+            Ok(JobState::Succeeded)
+        } else {
+            Ok(JobState::Failed)
+        }
+    }
+
+    /// Let go of the job file, or queue it again if it was submitted again while it ran and a
+    /// new file is there
+    async fn release(&self, path: PathBuf, stop: &mut watch::Receiver<bool>) {
+        {
+            let mut active = lock(&self.active);
+
+            match active.get_mut(&path) {
+                Some(again) if *again && path.is_file() => *again = false,
+                _ => {
+                    active.remove(&path);
+
+                    return;
+                }
+            }
+        }
+
+        info!("{:?} changed while it ran, queueing it again", path);
+
+        record(&self.journal, &self.events, &path, None, JobState::Queued).await;
+
+        // the queue may be full, a stopped pool resumes the job on the next start instead
+        select! {
+            biased;
+            _ = stop.wait_for(|stop| *stop) => {}
+            _ = self.queue.send(path) => {}
+        }
+    }
+
+    /// Resolve once the running jobs have to be cancelled
+    async fn cancelled(&self) {
+        let mut cancel = self.cancel.clone();
+
+        if cancel.wait_for(|cancel| *cancel).await.is_err() {
+            // the dispatcher is gone, so nothing will ever cancel
+            pending::<()>().await;
+        }
+    }
+}
+
+async fn remove_ready_marker(path: &Path, ready_marker: bool) {
+    if !ready_marker {
+        return;
+    }
+
+    let marker = ready_marker_path(path);
+
+    if let Err(error) = remove_file(&marker).await {
+        warn!("failed to remove ready marker {:?}: {}", marker, error);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // the data stays consistent even if a holder panicked
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Record the state of the job and tell the subscribers, a broken journal must not stop the job itself
+async fn record(
+    journal: &Journal,
+    events: &Events,
+    path: &Path,
+    hash: Option<&str>,
+    state: JobState,
+) {
+    events.emit(path, state);
+
+    if let Err(error) = journal.record(path, hash, state).await {
+        error!("failed to record {:?} as {:?}: {}", path, state, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::env::current_dir;
+    use tokio::fs::{create_dir_all, remove_dir_all, write};
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_reconcile() {
+        let path = current_dir().unwrap().join("test_reconcile");
+
+        let _ = remove_dir_all(&path).await;
+
+        create_dir_all(&path).await.unwrap();
+
+        let config_path = path.join("config.toml");
+
+        write(
+            &config_path,
+            format!(
+                r#"
+                listen_path = "{}"
+                processor_dir_path = "{}"
+                whitelist = ["*.json"]
+                stable_period_ms = 0
+                "#,
+                path.join("listen").to_string_lossy(),
+                path.join("processor").to_string_lossy(),
+            ),
+        )
+        .await
+        .unwrap();
+
+        let config = Config::load(config_path).await.unwrap();
+
+        let listen = config.rules()[0].path().to_owned();
+
+        let journal = Arc::new(Journal::open(config.state_path(), 10).await.unwrap());
+
+        let events = Arc::new(Events::new());
+
+        let (_config, receiver) = watch::channel(Arc::new(config));
+
+        let (pool, dispatcher) = WorkerPool::new(
+            Arc::new(Processors::new()),
+            Arc::clone(&journal),
+            receiver,
+            Arc::clone(&events),
+        );
+
+        // queued before the events were lost, then removed meanwhile
+        let gone = listen.join("gone.json");
+
+        // pending too, but in the scope of another rescan
+        let elsewhere = path.join("elsewhere/job.json");
+
+        for pending in [&gone, &elsewhere] {
+            journal
+                .record(pending, None, JobState::Queued)
+                .await
+                .unwrap();
+        }
+
+        let found = listen.join("found.json");
+
+        write(&found, "{}").await.unwrap();
+
+        let mut subscriber = events.subscribe();
+
+        pool.reconcile(vec![found.clone()], |path| path.starts_with(&listen))
+            .await
+            .unwrap();
+
+        let mut seen = Vec::new();
+
+        while let Some(event) = subscriber.next().await {
+            seen.push((event.path.clone(), event.state));
+
+            if event.path == found {
+                break;
+            }
+        }
+
+        assert_eq!(
+            seen,
+            [(gone.clone(), JobState::Failed), (found, JobState::Queued)]
+        );
+
+        let pending = journal.pending().await;
+
+        assert!(pending.contains(&elsewhere) && !pending.contains(&gone));
+
+        drop(pool);
+
+        dispatcher.shutdown(Duration::from_secs(5)).await;
+
+        remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn test_failed_job() {
+        let path = current_dir().unwrap().join("test_failed_job");
+
+        let _ = remove_dir_all(&path).await;
+
+        create_dir_all(&path).await.unwrap();
+
+        let config_path = path.join("config.toml");
+
+        write(
+            &config_path,
+            format!(
+                r#"
+                listen_path = "{}"
+                processor_dir_path = "{}"
+                whitelist = ["*.json"]
+                stable_period_ms = 0
+                "#,
+                path.join("listen").to_string_lossy(),
+                path.join("processor").to_string_lossy(),
+            ),
+        )
+        .await
+        .unwrap();
+
+        let config = Config::load(config_path).await.unwrap();
+
+        let listen = config.rules()[0].path().to_owned();
+
+        let dead_letter = config.rules()[0].dead_letter_path().to_owned();
+
+        let journal = Arc::new(Journal::open(config.state_path(), 10).await.unwrap());
+
+        let events = Arc::new(Events::new());
+
+        let (_config, receiver) = watch::channel(Arc::new(config));
+
+        let (pool, dispatcher) = WorkerPool::new(
+            Arc::new(Processors::new()),
+            journal,
+            receiver,
+            Arc::clone(&events),
+        );
+
+        let broken = listen.join("broken.json");
+
+        write(&broken, "{ oops").await.unwrap();
+
+        let mut subscriber = events.subscribe();
+
+        pool.submit(broken.clone()).await.unwrap();
+
+        while let Some(event) = subscriber.next().await {
+            if event.state == JobState::Failed {
+                break;
+            }
+        }
+
+        // not even parsed, still archived as failed, with a copy to replay
+        assert!(!broken.exists());
+
+        assert!(listen.join("failed/broken.json").exists());
+
+        let copies = std::fs::read_dir(&dead_letter)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+
+                name.to_string_lossy().ends_with("-broken.json")
+            })
+            .count();
+
+        assert_eq!(copies, 1);
+
+        drop(pool);
+
+        dispatcher.shutdown(Duration::from_secs(5)).await;
+
+        remove_dir_all(path).await.unwrap();
+    }
+}